@@ -18,13 +18,22 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE. }}}
 
-use super::HttpFile;
+use super::{
+    source::{self, ReadAt},
+    Options,
+};
 use anyhow::Result;
+use async_compression::tokio::bufread::{BzDecoder, GzipDecoder, LzmaDecoder};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::ops::Range;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
-use tokio::io::{duplex, AsyncRead, AsyncReadExt, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::io::{duplex, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadBuf};
 
 use xz2::stream::{Action, Status};
+use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
 
 ///
 const MAGIC: [u8; 8] = *b"!<arch>\n";
@@ -33,8 +42,14 @@ const MAGIC: [u8; 8] = *b"!<arch>\n";
 const TRAILER: [u8; 2] = [0x60, 0x0A];
 
 pub struct Deb {
-    file: HttpFile,
+    file: Arc<dyn ReadAt>,
     offset: u64,
+
+    /// GNU long name table, from the `//` member.
+    names: Option<Vec<u8>>,
+
+    /// running hash of everything we've read, if we're checking it.
+    checksum: Option<Checksum>,
 }
 
 trait AsyncReadSend = AsyncRead + Unpin + Send + 'static;
@@ -66,20 +81,451 @@ pub struct Header {
     pub mode: u64,
 }
 
+impl Header {
+    ///
+    fn parse(raw: [u8; 60]) -> Result<Self> {
+        if raw[field::TRAILER] != TRAILER {
+            anyhow::bail!("trailer is wrong; file corrupted?");
+        }
+
+        let raw2str = |range: Range<usize>| {
+            Ok::<&str, anyhow::Error>(std::str::from_utf8(&raw[range])?.trim())
+        };
+        // GNU ar leaves these blank where they don't mean anything, like
+        // on the `//` long name table.
+        let raw2num = |range: Range<usize>| match raw2str(range)? {
+            "" => Ok::<u64, anyhow::Error>(0),
+            v => Ok(v.parse()?),
+        };
+
+        Ok(Header {
+            identifier: raw2str(field::IDENTIFIER)?.to_owned(),
+            size: raw2str(field::SIZE)?.parse()?,
+            timestamp: raw2num(field::TIMESTAMP)?,
+            owner: raw2num(field::OWNER)?,
+            group: raw2num(field::GROUP)?,
+            mode: raw2num(field::MODE)?,
+        })
+    }
+
+    /// Check that the member body fits in the `available` bytes left in
+    /// the file.
+    fn fits(&self, available: u64) -> Result<(), Truncated> {
+        if self.size > available {
+            return Err(Truncated {
+                identifier: self.identifier.clone(),
+                size: self.size,
+                available,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Returned when a member claims to be larger than what is left of the
+/// file, which is usually a partially synced mirror.
 #[derive(Debug, Clone)]
-#[repr(packed)]
-struct RawHeader {
-    identifier: [u8; 16],
-    timestamp: [u8; 12],
-    owner: [u8; 6],
-    group: [u8; 6],
-    mode: [u8; 8],
-    size: [u8; 10],
-    trailer: [u8; 2],
+pub struct Truncated {
+    pub identifier: String,
+    pub size: u64,
+    pub available: u64,
+}
+
+impl std::fmt::Display for Truncated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "member {} is truncated: expected {} bytes, only {} available",
+            self.identifier, self.size, self.available
+        )
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+/// Returned when a compressed stream ends before the decoder says it's
+/// done, which is a member cut short just as much as a [Truncated] one.
+#[derive(Debug, Clone)]
+pub struct TruncatedStream {
+    pub codec: &'static str,
+}
+
+impl std::fmt::Display for TruncatedStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} stream is truncated", self.codec)
+    }
+}
+
+impl std::error::Error for TruncatedStream {}
+
+/// Where each fixed-width field sits in the 60 byte member header.
+mod field {
+    use std::ops::Range;
+
+    pub const IDENTIFIER: Range<usize> = 0..16;
+    pub const TIMESTAMP: Range<usize> = 16..28;
+    pub const OWNER: Range<usize> = 28..34;
+    pub const GROUP: Range<usize> = 34..40;
+    pub const MODE: Range<usize> = 40..48;
+    pub const SIZE: Range<usize> = 48..58;
+    pub const TRAILER: Range<usize> = 58..60;
+}
+
+/// Returned when the first member of an archive isn't a `debian-binary`
+/// with a version we understand.
+#[derive(Debug, Clone)]
+pub struct NotADeb {
+    pub version: String,
+}
+
+impl std::fmt::Display for NotADeb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not a Debian package (version {})", self.version)
+    }
+}
+
+impl std::error::Error for NotADeb {}
+
+/// Returned when the `data.tar` member is compressed with something we
+/// don't have a decoder for.
+#[derive(Debug, Clone)]
+pub struct Unsupported {
+    pub identifier: String,
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let compression = self.identifier.trim_start_matches("data.tar.");
+        write!(
+            f,
+            "unsupported compression {:?} on {}",
+            compression, self.identifier
+        )
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Returned when a `.deb` isn't the size or doesn't hash to what the index
+/// says it should.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub what: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} mismatch: expected {}, got {}",
+            self.what, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// SHA256 of a `.deb`, built up as its bytes are read. Reads only count
+/// when they pick up where the last one left off; anything skipped over
+/// is fetched again by [Checksum::catch_up] so that the whole file gets
+/// hashed, in order, by the time [Checksum::finish] is called.
+#[derive(Clone)]
+pub struct Checksum {
+    file: Arc<dyn ReadAt>,
+    expected: String,
+    /// hash so far, and the offset it covers up to.
+    state: Arc<Mutex<(Sha256, u64)>>,
+}
+
+impl Checksum {
+    ///
+    fn update(&self, offset: u64, buf: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let end = offset + buf.len() as u64;
+        if offset <= state.1 && state.1 < end {
+            let skip = (state.1 - offset) as usize;
+            state.0.update(&buf[skip..]);
+            state.1 = end;
+        }
+    }
+
+    /// Hash everything up to `to` that hasn't been read yet.
+    async fn catch_up(&self, to: u64) -> Result<()> {
+        let mut offset = self.state.lock().unwrap().1;
+        if offset >= to {
+            return Ok(());
+        }
+        let mut reader = match self.file.reader_at_to(offset, to - offset).await? {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let mut buf = vec![0u8; 1024 * 32];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.update(offset, &buf[..n]);
+            offset += n as u64;
+        }
+    }
+
+    /// Hash the rest of the file, and check it against what we expected.
+    pub async fn finish(&self) -> Result<()> {
+        self.catch_up(self.file.content_length()).await?;
+        let actual = format!("{:x}", self.state.lock().unwrap().0.clone().finalize());
+        if actual != self.expected {
+            return Err(Mismatch {
+                what: "SHA256",
+                expected: self.expected.clone(),
+                actual,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Wrap `inner` so that it only hits a clean EOF if the whole file
+    /// checks out.
+    pub fn verify<R>(self, inner: R) -> Verified<R> {
+        Verified {
+            inner,
+            checksum: self,
+            finish: None,
+            done: None,
+        }
+    }
+}
+
+/// Reader that checks a [Checksum] once `inner` hits EOF, turning a
+/// mismatch into an error.
+#[pin_project::pin_project]
+pub struct Verified<R> {
+    #[pin]
+    inner: R,
+    checksum: Checksum,
+    finish: Option<Pin<Box<dyn Future<Output = Result<()>> + Send>>>,
+    /// how the check went, once it's done; a mismatch is handed back on
+    /// every read from then on, not just the first.
+    done: Option<std::result::Result<(), String>>,
+}
+
+impl<R: AsyncRead> AsyncRead for Verified<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        let this = self.project();
+        match this.done {
+            None => {}
+            Some(Ok(())) => return Poll::Ready(Ok(())),
+            Some(Err(err)) => {
+                return Poll::Ready(Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::InvalidData,
+                    err.clone(),
+                )))
+            }
+        }
+
+        if this.finish.is_none() {
+            let filled = buf.filled().len();
+            match this.inner.poll_read(cx, buf) {
+                Poll::Ready(Ok(())) if buf.filled().len() == filled => {}
+                other => return other,
+            }
+            let checksum = this.checksum.clone();
+            *this.finish = Some(Box::pin(async move { checksum.finish().await }));
+        }
+
+        let finished = match this.finish.as_mut().unwrap().as_mut().poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(v) => v,
+        };
+        *this.finish = None;
+        match finished {
+            Ok(()) => {
+                *this.done = Some(Ok(()));
+                Poll::Ready(Ok(()))
+            }
+            Err(err) => {
+                tracing::warn!("failed to verify .deb: {}", err);
+                *this.done = Some(Err(err.to_string()));
+                Poll::Ready(Err(tokio::io::Error::new(
+                    tokio::io::ErrorKind::InvalidData,
+                    err,
+                )))
+            }
+        }
+    }
+}
+
+/// Reader that feeds everything it reads into a [Checksum].
+#[pin_project::pin_project]
+struct Hashed<R> {
+    #[pin]
+    inner: R,
+    offset: u64,
+    checksum: Checksum,
+}
+
+impl<R: AsyncRead> AsyncRead for Hashed<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let ret = this.inner.poll_read(cx, buf);
+        let read = &buf.filled()[filled..];
+        this.checksum.update(*this.offset, read);
+        *this.offset += read.len() as u64;
+        ret
+    }
 }
 
 type JoinSet = tokio::task::JoinSet<Result<()>>;
 
+/// Sizes of the buffers in the decompress pipeline. Bigger ones mean fewer
+/// wakeups on a fast link; smaller ones, less memory per open file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Buffers {
+    /// pipe between the decoder task and the reader.
+    pipe: usize,
+    /// compressed bytes read from the member at a time.
+    input: usize,
+    /// decompressed bytes handed to the pipe at a time.
+    output: usize,
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self {
+            pipe: 1024 * 32,
+            input: 1024 * 32,
+            output: 1024 * 128,
+        }
+    }
+}
+
+impl Buffers {
+    ///
+    pub fn with_pipe(mut self, pipe: usize) -> Self {
+        self.pipe = pipe.max(1);
+        self
+    }
+
+    ///
+    pub fn with_input(mut self, input: usize) -> Self {
+        self.input = input.max(1);
+        self
+    }
+
+    ///
+    pub fn with_output(mut self, output: usize) -> Self {
+        self.output = output.max(1);
+        self
+    }
+}
+
+/// A streaming decoder for [Decompress] to drive: fed the compressed
+/// bytes a chunk at a time, it hands back whatever it can decode.
+pub trait Codec: Send + 'static {
+    /// Name of the format, for errors.
+    fn name(&self) -> &'static str;
+
+    /// Decode as much of `input` as fits in the spare capacity of
+    /// `output`, returning how many bytes of `input` were used.
+    fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<usize>;
+
+    /// Whether the stream has ended, which is the only place it's safe to
+    /// stop.
+    fn at_end(&self) -> bool;
+
+    /// Get ready for another stream, concatenated after the one that just
+    /// ended.
+    fn restart(&mut self) -> Result<()>;
+}
+
+/// [Codec] for `.xz`.
+struct Xz {
+    stream: xz2::stream::Stream,
+    status: Status,
+}
+
+impl Xz {
+    ///
+    fn new() -> Result<Self> {
+        Ok(Xz {
+            stream: xz2::stream::Stream::new_stream_decoder(u64::MAX, 0)?,
+            status: Status::Ok,
+        })
+    }
+}
+
+impl Codec for Xz {
+    fn name(&self) -> &'static str {
+        "xz"
+    }
+
+    fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<usize> {
+        let before = self.stream.total_in();
+        self.status = self.stream.process_vec(input, output, Action::Run)?;
+        Ok((self.stream.total_in() - before) as usize)
+    }
+
+    fn at_end(&self) -> bool {
+        matches!(self.status, Status::StreamEnd)
+    }
+
+    fn restart(&mut self) -> Result<()> {
+        *self = Xz::new()?;
+        Ok(())
+    }
+}
+
+/// [Codec] for `.zst`.
+struct Zstd {
+    decoder: zstd::stream::raw::Decoder<'static>,
+    at_end: bool,
+}
+
+impl Zstd {
+    ///
+    fn new() -> Result<Self> {
+        Ok(Zstd {
+            decoder: zstd::stream::raw::Decoder::new()?,
+            at_end: false,
+        })
+    }
+}
+
+impl Codec for Zstd {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<usize> {
+        let mut input = InBuffer::around(input);
+        let mut output = OutBuffer::around(output);
+        // a hint of 0 means the frame is done.
+        self.at_end = self.decoder.run(&mut input, &mut output)? == 0;
+        Ok(input.pos())
+    }
+
+    fn at_end(&self) -> bool {
+        self.at_end
+    }
+
+    fn restart(&mut self) -> Result<()> {
+        // the decoder carries on into the next frame by itself.
+        Ok(())
+    }
+}
+
 #[pin_project::pin_project]
 pub struct Decompress {
     join_set: JoinSet,
@@ -89,31 +535,74 @@ pub struct Decompress {
 }
 
 impl Decompress {
-    pub async fn new<T: AsyncReadSend>(mut body: T) -> Result<Self> {
-        let mut decoder = xz2::stream::Stream::new_stream_decoder(u64::MAX, 0).unwrap();
-        let (pipe, mut pipe1) = duplex(1024 * 32);
+    ///
+    pub async fn xz<T: AsyncReadSend>(body: T, buffers: Buffers) -> Result<Self> {
+        Self::with_codec(body, Xz::new()?, buffers).await
+    }
+
+    ///
+    pub async fn zstd<T: AsyncReadSend>(body: T, buffers: Buffers) -> Result<Self> {
+        Self::with_codec(body, Zstd::new()?, buffers).await
+    }
+
+    /// Decode `body` with `codec` on a task of its own, handing the output
+    /// over a pipe as the reader asks for it.
+    pub async fn with_codec<T: AsyncReadSend, C: Codec>(
+        mut body: T,
+        mut codec: C,
+        buffers: Buffers,
+    ) -> Result<Self> {
+        let (pipe, mut pipe1) = duplex(buffers.pipe);
         let mut join_set = JoinSet::new();
 
         join_set.build_task().name("").spawn(async move {
-            loop {
-                let mut compressed = vec![0u8; 1024 * 32];
-                let mut output: Vec<u8> = Vec::with_capacity(1024 * 128);
+            let mut compressed = vec![0u8; buffers.input];
+            let mut output: Vec<u8> = Vec::with_capacity(buffers.output);
 
+            loop {
                 let n = body.read(&mut compressed).await?;
-                let compressed = &compressed[..n];
-                if n == 0 {
-                    break;
-                }
-                let n = n as u64;
-                let end = decoder.total_in() + n;
-                while decoder.total_in() < end {
-                    let start = compressed.len() - (end - decoder.total_in()) as usize;
+                let input = &compressed[..n];
+
+                // feed everything we just read to the decoder, handing off
+                // each chunk of output as it comes. write_all waits on the
+                // reader, so we never get far ahead of it. Once the input is
+                // gone, keep going until the decoder has nothing left to
+                // flush.
+                let mut offset = 0;
+                loop {
+                    if codec.at_end() {
+                        // another stream may be concatenated after this
+                        // one, past some null padding.
+                        while input.get(offset) == Some(&0) {
+                            offset += 1;
+                        }
+                        if offset == input.len() {
+                            break;
+                        }
+                        codec.restart()?;
+                    }
+
                     output.clear();
-                    decoder.process_vec(&compressed[start..], &mut output, Action::Run)?;
+                    offset += codec.decode(&input[offset..], &mut output)?;
                     pipe1.write_all(&output).await?;
+
+                    if offset == input.len() && output.len() < output.capacity() {
+                        break;
+                    }
+                }
+
+                if n == 0 {
+                    break;
                 }
             }
 
+            // without the end of the stream, what we've handed out so far
+            // is just a prefix of the file.
+            if !codec.at_end() {
+                anyhow::bail!(TruncatedStream {
+                    codec: codec.name()
+                });
+            }
             Ok(())
         })?;
 
@@ -128,14 +617,64 @@ impl AsyncRead for Decompress {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<tokio::io::Result<()>> {
         let this = self.project();
-        this.pipe.poll_read(cx, buf)
+        let filled = buf.filled().len();
+        match this.pipe.poll_read(cx, buf) {
+            Poll::Ready(Ok(())) if buf.filled().len() == filled => {}
+            other => return other,
+        }
+
+        // the pipe is closed, but that's only a clean EOF if the decoder
+        // task didn't fail.
+        match this.join_set.poll_join_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(None) | Poll::Ready(Some(Ok(Ok(())))) => Poll::Ready(Ok(())),
+            // kept as-is rather than as an anyhow::Error, so that it can
+            // be told apart further up.
+            Poll::Ready(Some(Ok(Err(err)))) => {
+                let err = match err.downcast::<TruncatedStream>() {
+                    Ok(truncated) => {
+                        tokio::io::Error::new(tokio::io::ErrorKind::InvalidData, truncated)
+                    }
+                    Err(err) => tokio::io::Error::new(tokio::io::ErrorKind::InvalidData, err),
+                };
+                Poll::Ready(Err(err))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                Poll::Ready(Err(tokio::io::Error::new(tokio::io::ErrorKind::Other, err)))
+            }
+        }
     }
 }
 
+/// If `identifier` is the `data.tar` member of a `.deb`, return a reader
+/// for the decompressed tar, picking the decoder from the suffix. A
+/// `data.tar` with a suffix we can't decode is an [Unsupported] error.
+pub async fn data_tar(
+    identifier: &str,
+    body: Pin<Box<dyn AsyncReadSend>>,
+    buffers: Buffers,
+) -> Result<Option<Pin<Box<dyn AsyncReadSend>>>> {
+    let buffered = |body| BufReader::with_capacity(buffers.input, body);
+    Ok(Some(match identifier {
+        "data.tar.xz" => Box::pin(Decompress::xz(body, buffers).await?),
+        "data.tar.zst" => Box::pin(Decompress::zstd(body, buffers).await?),
+        "data.tar.gz" => Box::pin(GzipDecoder::new(buffered(body))),
+        // older packages, and some derivatives, still carry these.
+        "data.tar.bz2" => Box::pin(BzDecoder::new(buffered(body))),
+        "data.tar.lzma" => Box::pin(LzmaDecoder::new(buffered(body))),
+        "data.tar" => body,
+        other if other.starts_with("data.tar.") => anyhow::bail!(Unsupported {
+            identifier: other.to_owned()
+        }),
+        _ => return Ok(None),
+    }))
+}
+
 impl Deb {
-    ///
-    pub async fn open(host: &str) -> Result<Deb> {
-        let file = HttpFile::connect(host).await?;
+    /// Open the `.deb` at `url`, which may be a `file://` URL for a mirror
+    /// on the local filesystem, talking to the mirror with `options`.
+    pub async fn open(url: &str, options: &Options) -> Result<Deb> {
+        let file = source::open(url, options).await?;
 
         let mut prefix = [0u8; 8];
         file.reader_at_to(0, 8)
@@ -148,52 +687,510 @@ impl Deb {
             anyhow::bail!("wrong file magic; is this an .ar file?");
         }
 
-        Ok(Deb { file, offset: 8 })
+        Ok(Deb {
+            file,
+            offset: 8,
+            names: None,
+            checksum: None,
+        })
     }
 
-    ///
-    pub async fn next(&mut self) -> Result<Option<DebEntry>> {
-        let mut header = [0u8; 60];
-        let mut reader = match self.file.reader_at_to(self.offset, 60).await? {
-            None => return Ok(None),
+    /// Read the first member, and make sure it's a `debian-binary` saying
+    /// this is a format 2.x package, before going any further.
+    pub async fn check_format(mut self) -> Result<Self> {
+        let entry = match self.next().await? {
+            None => anyhow::bail!(NotADeb {
+                version: "none".to_owned()
+            }),
             Some(v) => v,
         };
+        if entry.header().identifier != "debian-binary" {
+            anyhow::bail!(NotADeb {
+                version: format!("none, first member is {:?}", entry.header().identifier),
+            });
+        }
 
-        reader.read_exact(&mut header).await?;
+        let mut version = vec![];
+        entry.into_body().take(64).read_to_end(&mut version).await?;
+        let version = String::from_utf8_lossy(&version).trim().to_owned();
+        if !version.starts_with("2.") {
+            anyhow::bail!(NotADeb { version });
+        }
+        Ok(self)
+    }
 
-        let header = unsafe { std::mem::transmute::<[u8; 60], RawHeader>(header) };
+    /// Check the whole file against `size` and `sha256` as it's read. The
+    /// size is checked right away; call [Checksum::finish] on
+    /// [Deb::checksum] once done with the members for the hash.
+    pub fn with_checksum(mut self, size: Option<u64>, sha256: &str) -> Result<Self> {
+        if let Some(size) = size {
+            if size != self.file.content_length() {
+                return Err(Mismatch {
+                    what: "size",
+                    expected: size.to_string(),
+                    actual: self.file.content_length().to_string(),
+                }
+                .into());
+            }
+        }
+        self.checksum = Some(Checksum {
+            file: self.file.clone(),
+            expected: sha256.to_lowercase(),
+            state: Arc::new(Mutex::new((Sha256::new(), 0))),
+        });
+        Ok(self)
+    }
 
-        if header.trailer != TRAILER {
-            anyhow::bail!("trailer is wrong; file corrupted?");
+    ///
+    pub fn checksum(&self) -> Option<Checksum> {
+        self.checksum.clone()
+    }
+
+    ///
+    pub async fn next(&mut self) -> Result<Option<DebEntry>> {
+        loop {
+            if let Some(checksum) = &self.checksum {
+                // whatever the last member's reader didn't get to.
+                checksum.catch_up(self.offset).await?;
+            }
+
+            let mut header = [0u8; 60];
+            let mut reader = match self.file.reader_at_to(self.offset, 60).await? {
+                None => return Ok(None),
+                Some(v) => v,
+            };
+
+            reader.read_exact(&mut header).await?;
+            // done with this response, so the connection can be reused.
+            drop(reader);
+            if let Some(checksum) = &self.checksum {
+                checksum.update(self.offset, &header);
+            }
+
+            let mut header = Header::parse(header)?;
+            let size = header.size;
+
+            self.offset += 60;
+            header.fits(self.file.content_length().saturating_sub(self.offset))?;
+
+            let reader: Pin<Box<dyn AsyncReadSend>> =
+                match self.file.reader_at_to(self.offset, size).await? {
+                    Some(v) => Box::pin(v),
+                    // an empty member whose header ends right at EOF.
+                    None if size == 0 => Box::pin(tokio::io::empty()),
+                    None => return Ok(None),
+                };
+            let mut reader: Pin<Box<dyn AsyncReadSend>> = match &self.checksum {
+                None => Box::pin(reader),
+                Some(checksum) => Box::pin(Hashed {
+                    inner: reader,
+                    offset: self.offset,
+                    checksum: checksum.clone(),
+                }),
+            };
+            // member bodies are padded out to an even offset.
+            self.offset += size + size % 2;
+
+            if header.identifier == "//" {
+                let mut names = Vec::with_capacity(size as usize);
+                reader.read_to_end(&mut names).await?;
+                self.names = Some(names);
+                continue;
+            }
+            header.identifier = resolve_name(self.names.as_deref(), &header.identifier)?;
+
+            return Ok(Some(DebEntry {
+                body: reader,
+                header,
+            }));
         }
+    }
+}
 
-        let raw2str = |raw| Ok::<&str, anyhow::Error>(std::str::from_utf8(raw)?.trim());
+/// Turn a GNU ar identifier into the real member name. Long names are
+/// stored as `/N`, an offset into the `//` name table where the name is
+/// terminated by `/\n`, and short names have a trailing `/`.
+fn resolve_name(names: Option<&[u8]>, identifier: &str) -> Result<String> {
+    if identifier == "/" || identifier == "//" {
+        return Ok(identifier.to_owned());
+    }
 
-        let identifier = raw2str(&header.identifier)?.to_owned();
-        let size: u64 = raw2str(&header.size)?.parse()?;
-        let timestamp: u64 = raw2str(&header.timestamp)?.parse()?;
-        let owner: u64 = raw2str(&header.owner)?.parse()?;
-        let group: u64 = raw2str(&header.group)?.parse()?;
-        let mode: u64 = raw2str(&header.mode)?.parse()?;
+    let offset = match identifier.strip_prefix('/') {
+        None => return Ok(identifier.trim_end_matches('/').to_owned()),
+        Some(offset) => offset.parse::<usize>()?,
+    };
 
-        self.offset += 60;
-        let mut reader = match self.file.reader_at_to(self.offset, size).await? {
-            None => return Ok(None),
-            Some(v) => v,
-        };
-        self.offset += size;
-
-        Ok(Some(DebEntry {
-            body: Box::pin(reader),
-            header: Header {
-                identifier,
-                size,
-                timestamp,
-                owner,
-                group,
-                mode,
-            },
-        }))
+    let names = names.ok_or(anyhow::anyhow!(
+        "long name {} without a name table",
+        identifier
+    ))?;
+    let name = names.get(offset..).ok_or(anyhow::anyhow!(
+        "long name {} is past the name table",
+        identifier
+    ))?;
+    let end = name
+        .windows(2)
+        .position(|w| w == b"/\n")
+        .ok_or(anyhow::anyhow!("long name {} isn't terminated", identifier))?;
+    Ok(std::str::from_utf8(&name[..end])?.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        resolve_name, Buffers, Deb, Decompress, Header, NotADeb, Options, Truncated,
+        TruncatedStream,
+    };
+    use crate::testing::{self, Mirror};
+    use tokio::io::AsyncReadExt;
+
+    fn raw(identifier: &str, size: u64) -> [u8; 60] {
+        format!(
+            "{identifier:<16}{:<12}{:<6}{:<6}{:<8}{size:<10}`\n",
+            0, 0, 0, 100644
+        )
+        .into_bytes()
+        .try_into()
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_header() {
+        let header = Header::parse(raw("data.tar.xz", 1000)).unwrap();
+        assert_eq!("data.tar.xz", header.identifier);
+        assert_eq!(1000, header.size);
+        assert_eq!(100644, header.mode);
+    }
+
+    #[test]
+    fn header_fields() {
+        let mut raw = raw("debian-binary", 4);
+        raw[16..28].copy_from_slice(b"1700000000  ");
+        raw[28..34].copy_from_slice(b"1000  ");
+        raw[34..40].copy_from_slice(b"1001  ");
+        let header = Header::parse(raw).unwrap();
+        assert_eq!("debian-binary", header.identifier);
+        assert_eq!(4, header.size);
+        assert_eq!(1700000000, header.timestamp);
+        assert_eq!(1000, header.owner);
+        assert_eq!(1001, header.group);
+
+        raw[59] = b'!';
+        assert!(Header::parse(raw).is_err());
+    }
+
+    #[test]
+    fn header_blank_fields() {
+        let mut raw = raw("//", 32);
+        raw[16..48].copy_from_slice(&[b' '; 32]);
+        let header = Header::parse(raw).unwrap();
+        assert_eq!("//", header.identifier);
+        assert_eq!(32, header.size);
+        assert_eq!(0, header.timestamp);
+        assert_eq!(0, header.mode);
+
+        // the size is never left out.
+        raw[48..58].copy_from_slice(&[b' '; 10]);
+        assert!(Header::parse(raw).is_err());
+    }
+
+    #[tokio::test]
+    async fn header_at_eof() {
+        // an empty last member, whose header ends right at EOF.
+        let archive = testing::ar(&[("debian-binary", &b"2.0\n"[..]), ("empty", &b""[..])]);
+        let mut short = archive.clone();
+        short.extend_from_slice(&raw("data.tar.xz", 0)[..30]);
+
+        let socket = Mirror::new(&[("/a.deb", archive), ("/short.deb", short)]).listen_unix();
+
+        let mut deb = Deb::open(&testing::unix_uri(&socket, "/a.deb"), &Options::default())
+            .await
+            .unwrap();
+        assert!(deb.next().await.unwrap().is_some());
+        let entry = deb.next().await.unwrap().unwrap();
+        assert_eq!("empty", entry.header().identifier);
+        let mut body = vec![];
+        entry.into_body().read_to_end(&mut body).await.unwrap();
+        assert!(body.is_empty());
+        assert!(deb.next().await.unwrap().is_none());
+
+        // and one cut off half way through.
+        let mut deb = Deb::open(
+            &testing::unix_uri(&socket, "/short.deb"),
+            &Options::default(),
+        )
+        .await
+        .unwrap();
+        assert!(deb.next().await.unwrap().is_some());
+        assert!(deb.next().await.unwrap().is_some());
+        assert!(deb.next().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn check_format() {
+        let good = testing::deb(&[]).await;
+        let future = testing::ar(&[("debian-binary", &b"3.0\n"[..])]);
+        let missing = testing::ar(&[("data.tar.xz", &b""[..])]);
+        let socket = Mirror::new(&[
+            ("/good.deb", good),
+            ("/future.deb", future),
+            ("/missing.deb", missing),
+        ])
+        .listen_unix();
+
+        let mut deb = Deb::open(
+            &testing::unix_uri(&socket, "/good.deb"),
+            &Options::default(),
+        )
+        .await
+        .unwrap()
+        .check_format()
+        .await
+        .unwrap();
+        let entry = deb.next().await.unwrap().unwrap();
+        assert_eq!("control.tar.xz", entry.header().identifier);
+
+        for (path, version) in [
+            ("/future.deb", "3.0"),
+            ("/missing.deb", "none, first member is \"data.tar.xz\""),
+        ] {
+            let err = Deb::open(&testing::unix_uri(&socket, path), &Options::default())
+                .await
+                .unwrap()
+                .check_format()
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(version, err.downcast_ref::<NotADeb>().unwrap().version);
+        }
+    }
+
+    #[test]
+    fn truncated_member() {
+        let header = Header::parse(raw("data.tar.xz", 1000)).unwrap();
+        assert!(header.fits(1000).is_ok());
+
+        let err: anyhow::Error = header.fits(500).unwrap_err().into();
+        let truncated = err.downcast_ref::<Truncated>().unwrap();
+        assert_eq!("data.tar.xz", truncated.identifier);
+        assert_eq!(1000, truncated.size);
+        assert_eq!(500, truncated.available);
+    }
+
+    #[tokio::test]
+    async fn decompress_errors() {
+        let data: Vec<u8> = (0..100_000u32).flat_map(|v| v.to_le_bytes()).collect();
+        let compressed = testing::xz(&data);
+
+        let mut out = vec![];
+        Decompress::xz(std::io::Cursor::new(compressed.clone()), Buffers::default())
+            .await
+            .unwrap()
+            .read_to_end(&mut out)
+            .await
+            .unwrap();
+        assert_eq!(data, out);
+
+        let truncated = compressed[..compressed.len() / 2].to_vec();
+        let mut out = vec![];
+        assert!(
+            Decompress::xz(std::io::Cursor::new(truncated), Buffers::default())
+                .await
+                .unwrap()
+                .read_to_end(&mut out)
+                .await
+                .is_err()
+        );
+
+        let mut corrupt = compressed.clone();
+        let middle = corrupt.len() / 2;
+        corrupt[middle] ^= 0xff;
+        let mut out = vec![];
+        assert!(
+            Decompress::xz(std::io::Cursor::new(corrupt), Buffers::default())
+                .await
+                .unwrap()
+                .read_to_end(&mut out)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn multi_stream() {
+        let first: Vec<u8> = (0..10_000u32).flat_map(|v| v.to_le_bytes()).collect();
+        let second: Vec<u8> = (0..10_000u32).flat_map(|v| v.to_be_bytes()).collect();
+        let expected = [first.clone(), second.clone()].concat();
+
+        for padding in [0, 4, 8] {
+            let mut compressed = testing::xz(&first);
+            compressed.extend(std::iter::repeat(0).take(padding));
+            compressed.extend(testing::xz(&second));
+
+            let mut out = vec![];
+            Decompress::xz(std::io::Cursor::new(compressed), Buffers::default())
+                .await
+                .unwrap()
+                .read_to_end(&mut out)
+                .await
+                .unwrap();
+            assert_eq!(expected, out);
+        }
+    }
+
+    #[tokio::test]
+    async fn small_buffers() {
+        let expected: Vec<u8> = (0..10_000u32).flat_map(|v| v.to_le_bytes()).collect();
+        let buffers = Buffers::default().with_pipe(7).with_input(3).with_output(5);
+
+        let mut out = vec![];
+        Decompress::xz(std::io::Cursor::new(testing::xz(&expected)), buffers)
+            .await
+            .unwrap()
+            .read_to_end(&mut out)
+            .await
+            .unwrap();
+        assert_eq!(expected, out);
+    }
+
+    #[tokio::test]
+    async fn zstd() {
+        let first: Vec<u8> = (0..10_000u32).flat_map(|v| v.to_le_bytes()).collect();
+        let second: Vec<u8> = (0..10_000u32).flat_map(|v| v.to_be_bytes()).collect();
+        let expected = [first.clone(), second.clone()].concat();
+        let compressed = [testing::zstd(&first).await, testing::zstd(&second).await].concat();
+
+        for buffers in [
+            Buffers::default(),
+            Buffers::default().with_pipe(7).with_input(3).with_output(5),
+        ] {
+            let mut out = vec![];
+            Decompress::zstd(std::io::Cursor::new(compressed.clone()), buffers)
+                .await
+                .unwrap()
+                .read_to_end(&mut out)
+                .await
+                .unwrap();
+            assert_eq!(expected, out);
+        }
+
+        let truncated = compressed[..compressed.len() / 4].to_vec();
+        let mut out = vec![];
+        let err = Decompress::zstd(std::io::Cursor::new(truncated), Buffers::default())
+            .await
+            .unwrap()
+            .read_to_end(&mut out)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("zstd stream is truncated"),
+            "{err}"
+        );
+        assert!(err.get_ref().is_some_and(|err| err.is::<TruncatedStream>()));
+    }
+
+    #[test]
+    fn long_names() {
+        let names = b"data.tar.xz.with-a-long-name/\ncontrol.tar.xz.with-a-long-name/\n";
+        assert_eq!(
+            "data.tar.xz.with-a-long-name",
+            resolve_name(Some(names), "/0").unwrap()
+        );
+        assert_eq!(
+            "control.tar.xz.with-a-long-name",
+            resolve_name(Some(names), "/30").unwrap()
+        );
+        assert_eq!("data.tar.xz", resolve_name(None, "data.tar.xz/").unwrap());
+        assert_eq!("data.tar.xz", resolve_name(None, "data.tar.xz").unwrap());
+        assert!(resolve_name(None, "/0").is_err());
+        assert!(resolve_name(Some(names), "/500").is_err());
+    }
+
+    #[tokio::test]
+    async fn odd_sized_members() {
+        let archive = testing::ar(&[
+            ("debian-binary", &b"2.0\n"[..]),
+            ("odd", &b"abc"[..]),
+            ("after-odd", &b"defgh"[..]),
+        ]);
+        let socket = Mirror::new(&[("/a.deb", archive)]).listen_unix();
+        let mut deb = Deb::open(&testing::unix_uri(&socket, "/a.deb"), &Options::default())
+            .await
+            .unwrap();
+
+        let mut found = vec![];
+        while let Some(entry) = deb.next().await.unwrap() {
+            let name = entry.header().identifier.clone();
+            let mut body = vec![];
+            entry.into_body().read_to_end(&mut body).await.unwrap();
+            found.push((name, body));
+        }
+        assert_eq!(
+            vec![
+                ("debian-binary".to_owned(), b"2.0\n".to_vec()),
+                ("odd".to_owned(), b"abc".to_vec()),
+                ("after-odd".to_owned(), b"defgh".to_vec()),
+            ],
+            found
+        );
+    }
+
+    #[tokio::test]
+    async fn overrunning_mirror() {
+        let archive = testing::ar(&[
+            ("debian-binary", &b"2.0\n"[..]),
+            ("control.tar.xz", &b"control"[..]),
+            ("data.tar.xz", &b"data"[..]),
+        ]);
+        let socket = Mirror::new(&[("/a.deb", archive)])
+            .with_overrun(100)
+            .listen_unix();
+        let mut deb = Deb::open(&testing::unix_uri(&socket, "/a.deb"), &Options::default())
+            .await
+            .unwrap();
+
+        let mut found = vec![];
+        while let Some(entry) = deb.next().await.unwrap() {
+            let mut body = vec![];
+            entry.into_body().read_to_end(&mut body).await.unwrap();
+            found.push(body);
+        }
+        assert_eq!(
+            vec![b"2.0\n".to_vec(), b"control".to_vec(), b"data".to_vec()],
+            found
+        );
+    }
+
+    #[tokio::test]
+    async fn gnu_archive() {
+        let names = b"a-rather-long-member-names.txt/\n".to_vec();
+        let archive = testing::ar(&[
+            ("debian-binary/", &b"2.0\n"[..]),
+            ("//", &names[..]),
+            ("/0", &b"long"[..]),
+        ]);
+        let socket = Mirror::new(&[("/a.deb", archive)]).listen_unix();
+        let mut deb = Deb::open(&testing::unix_uri(&socket, "/a.deb"), &Options::default())
+            .await
+            .unwrap();
+
+        let mut found = vec![];
+        while let Some(entry) = deb.next().await.unwrap() {
+            let name = entry.header().identifier.clone();
+            let mut body = vec![];
+            entry.into_body().read_to_end(&mut body).await.unwrap();
+            found.push((name, body));
+        }
+        assert_eq!(
+            vec![
+                ("debian-binary".to_owned(), b"2.0\n".to_vec()),
+                (
+                    "a-rather-long-member-names.txt".to_owned(),
+                    b"long".to_vec()
+                ),
+            ],
+            found
+        );
     }
 }
 
@@ -0,0 +1,374 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use async_compression::tokio::{bufread::ZstdDecoder, write::ZstdEncoder};
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Result},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+    time::SystemTime,
+};
+use tokio::io::{AsyncRead, AsyncWriteExt, BufReader, ReadBuf};
+
+/// Magic number of the zstd skippable frame on the end of a compressed
+/// entry, which holds how big the debug file is once it's decompressed.
+const SIZE_FRAME: u32 = 0x184d2a5d;
+
+/// A debug file out of a [DiskCache], as it's kept on disk.
+#[derive(Debug)]
+pub enum CachedFile {
+    /// stored as-is, to be read straight off the disk.
+    Raw(File),
+
+    /// stored zstd-compressed, along with how big it comes out to.
+    Zstd(File, u64),
+}
+
+impl CachedFile {
+    /// Size of the debug file, decompressed.
+    pub fn size(&self) -> Result<u64> {
+        match self {
+            Self::Raw(file) => Ok(file.metadata()?.len()),
+            Self::Zstd(_, len) => Ok(*len),
+        }
+    }
+
+    /// Another handle on the same file.
+    pub fn try_clone(&self) -> Result<Self> {
+        match self {
+            Self::Raw(file) => Ok(Self::Raw(file.try_clone()?)),
+            Self::Zstd(file, len) => Ok(Self::Zstd(file.try_clone()?, *len)),
+        }
+    }
+
+    /// Stream the debug file out from the top, decompressing it if need be.
+    pub fn reader(&self) -> Result<Pin<Box<dyn AsyncRead + Send>>> {
+        Ok(match self {
+            Self::Raw(file) => Box::pin(Positioned::new(file.try_clone()?)),
+            Self::Zstd(file, _) => Box::pin(ZstdDecoder::new(BufReader::new(Positioned::new(
+                file.try_clone()?,
+            )))),
+        })
+    }
+}
+
+/// Reads a file from the top with positional reads, so that it keeps an
+/// offset of its own no matter who else has the file open.
+struct Positioned {
+    file: File,
+    offset: u64,
+}
+
+impl Positioned {
+    ///
+    fn new(file: File) -> Self {
+        Positioned { file, offset: 0 }
+    }
+}
+
+impl AsyncRead for Positioned {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let n = self.file.read_at(buf.initialize_unfilled(), self.offset)?;
+        buf.advance(n);
+        self.offset += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The decompressed size of a compressed entry, out of the skippable frame
+/// [DiskCache::put] leaves on the end of it.
+fn zstd_len(file: &File) -> Result<u64> {
+    let mut frame = [0u8; 16];
+    let len = file.metadata()?.len();
+    file.read_exact_at(&mut frame, len.saturating_sub(16))?;
+    let (magic, rest) = frame.split_at(4);
+    let (size, len) = rest.split_at(4);
+    if magic != SIZE_FRAME.to_le_bytes() || size != 8u32.to_le_bytes() {
+        return Err(Error::new(ErrorKind::InvalidData, "no size frame"));
+    }
+    Ok(u64::from_le_bytes(len.try_into().unwrap()))
+}
+
+/// Directory of extracted debug files keyed by build-id, trimmed back to
+/// `max_size` bytes by evicting whatever was least recently opened.
+///
+/// The modification time doubles as the access time, since plenty of
+/// filesystems are mounted `noatime`; every hit bumps it.
+///
+/// Debug files can be kept zstd-compressed, which saves a good deal of
+/// disk at the cost of decompressing them as they're read.
+#[derive(Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    compress: bool,
+    evicting: tokio::sync::Mutex<()>,
+}
+
+impl DiskCache {
+    ///
+    pub fn new(dir: &Path, max_size: u64) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(DiskCache {
+            dir: dir.to_owned(),
+            max_size,
+            compress: false,
+            evicting: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Keep debug files zstd-compressed. Anything cached the other way is
+    /// left alone, to be evicted in time.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Whether debug files are kept compressed.
+    pub fn compressed(&self) -> bool {
+        self.compress
+    }
+
+    ///
+    fn path(&self, build_id: &str) -> PathBuf {
+        match self.compress {
+            true => self.dir.join(format!("{build_id}.debug.zst")),
+            false => self.dir.join(format!("{build_id}.debug")),
+        }
+    }
+
+    ///
+    fn open(&self, build_id: &str) -> Result<CachedFile> {
+        let file = File::open(self.path(build_id))?;
+        match self.compress {
+            true => {
+                let len = zstd_len(&file)?;
+                Ok(CachedFile::Zstd(file, len))
+            }
+            false => Ok(CachedFile::Raw(file)),
+        }
+    }
+
+    /// Open the cached copy of `build_id`, if there is one.
+    pub async fn get(&self, build_id: &str) -> Option<CachedFile> {
+        let file = match self.open(build_id) {
+            Err(err) if err.kind() == ErrorKind::NotFound => return None,
+            Err(err) => {
+                tracing::warn!("failed to open cached {}: {:?}", build_id, err);
+                return None;
+            }
+            Ok(v) => v,
+        };
+        let touched = match &file {
+            CachedFile::Raw(file) | CachedFile::Zstd(file, _) => {
+                file.set_modified(SystemTime::now())
+            }
+        };
+        if let Err(err) = touched {
+            tracing::warn!("failed to touch cached {}: {:?}", build_id, err);
+        }
+        Some(file)
+    }
+
+    /// Copy `body` into the cache as `build_id`, returning the cached copy.
+    /// The file only shows up under its real name once it is complete, so
+    /// a failed or concurrent write never leaves a partial file behind.
+    pub async fn put<R>(&self, build_id: &str, body: &mut R) -> Result<CachedFile>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let tmp = self.dir.join(format!(
+            ".{build_id}.{}.{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let written = async {
+            let mut file = tokio::fs::File::create(&tmp).await?;
+            match self.compress {
+                false => {
+                    tokio::io::copy(body, &mut file).await?;
+                }
+                true => {
+                    let mut encoder = ZstdEncoder::new(&mut file);
+                    let len = tokio::io::copy(body, &mut encoder).await?;
+                    encoder.shutdown().await?;
+                    let mut frame = SIZE_FRAME.to_le_bytes().to_vec();
+                    frame.extend_from_slice(&8u32.to_le_bytes());
+                    frame.extend_from_slice(&len.to_le_bytes());
+                    file.write_all(&frame).await?;
+                }
+            }
+            file.sync_all().await?;
+            tokio::fs::rename(&tmp, self.path(build_id)).await
+        }
+        .await;
+        if let Err(err) = written {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(err);
+        }
+
+        let file = self.open(build_id)?;
+        self.evict().await?;
+        Ok(file)
+    }
+
+    /// Drop `build_id` from the cache, if it's there. Anyone with it open
+    /// can keep reading it until they close it.
+    pub async fn remove(&self, build_id: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(build_id)).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            v => v,
+        }
+    }
+
+    /// Remove the least recently used files until the cache fits in
+    /// `max_size`. Anything already open stays readable until it's closed.
+    async fn evict(&self) -> Result<()> {
+        let _evicting = self.evicting.lock().await;
+
+        let mut files = vec![];
+        let mut total = 0;
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            total += metadata.len();
+            files.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+        files.sort();
+
+        for (_, len, path) in files {
+            if total <= self.max_size {
+                break;
+            }
+            tracing::debug!("evicting {:?} from the cache", path);
+            tokio::fs::remove_file(&path).await?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CachedFile, DiskCache};
+    use std::{
+        io::Read,
+        time::{Duration, SystemTime},
+    };
+    use tokio::io::AsyncReadExt;
+
+    fn dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let cache = DiskCache::new(&dir("cache-round-trip"), 1024).unwrap();
+        assert!(cache.get("1c54e04f").await.is_none());
+
+        let mut body = &b"debug info"[..];
+        let Ok(CachedFile::Raw(mut file)) = cache.put("1c54e04f", &mut body).await else {
+            panic!("not cached as-is");
+        };
+        let mut out = vec![];
+        file.read_to_end(&mut out).unwrap();
+        assert_eq!(b"debug info", &out[..]);
+
+        let Some(CachedFile::Raw(mut file)) = cache.get("1c54e04f").await else {
+            panic!("not cached as-is");
+        };
+        let mut out = vec![];
+        file.read_to_end(&mut out).unwrap();
+        assert_eq!(b"debug info", &out[..]);
+    }
+
+    #[tokio::test]
+    async fn compressed_round_trip() {
+        let dir = dir("cache-zstd");
+        let cache = DiskCache::new(&dir, 1 << 20)
+            .unwrap()
+            .with_compression(true);
+        assert!(cache.compressed());
+        let debug: Vec<u8> = (0..64 * 1024u32)
+            .flat_map(|v| (v % 7).to_le_bytes())
+            .collect();
+
+        let put = cache.put("1c54e04f", &mut &debug[..]).await.unwrap();
+        assert!(matches!(put, CachedFile::Zstd(_, _)));
+        let on_disk = std::fs::metadata(dir.join("1c54e04f.debug.zst"))
+            .unwrap()
+            .len();
+        assert!(on_disk < debug.len() as u64 / 10, "{on_disk}");
+
+        let file = cache.get("1c54e04f").await.unwrap();
+        assert_eq!(debug.len() as u64, file.size().unwrap());
+        // twice over, since each read starts back at the top.
+        for _ in 0..2 {
+            let mut out = vec![];
+            file.reader().unwrap().read_to_end(&mut out).await.unwrap();
+            assert_eq!(debug, out);
+        }
+
+        // entries kept the other way aren't picked up as compressed ones.
+        let raw = DiskCache::new(&dir, 1 << 20).unwrap();
+        assert!(raw.get("1c54e04f").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let cache = DiskCache::new(&dir("cache-evict"), 25).unwrap();
+        let past = SystemTime::now() - Duration::from_secs(60);
+
+        for (n, build_id) in ["aa", "bb"].iter().enumerate() {
+            let mut body = &[0u8; 10][..];
+            cache.put(build_id, &mut body).await.unwrap();
+            std::fs::File::open(cache.path(build_id))
+                .unwrap()
+                .set_modified(past + Duration::from_secs(n as u64))
+                .unwrap();
+        }
+
+        // opening aa makes bb the oldest.
+        assert!(cache.get("aa").await.is_some());
+        let mut body = &[0u8; 10][..];
+        cache.put("cc", &mut body).await.unwrap();
+
+        assert!(cache.get("aa").await.is_some());
+        assert!(cache.get("bb").await.is_none());
+        assert!(cache.get("cc").await.is_some());
+    }
+}
+
+// vim: foldmethod=marker
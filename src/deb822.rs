@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE. }}}
 
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap};
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, Error as IoError};
 
 #[derive(Debug)]
@@ -33,13 +33,159 @@ impl From<IoError> for Error {
     }
 }
 
-///
-pub async fn next<T>(mut b: T) -> Result<Option<HashMap<String, String>>, Error>
+/// The fields of a `Packages` stanza we need to serve its debug files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageStanza {
+    pub package: Option<String>,
+    pub version: Option<String>,
+    pub build_ids: Vec<String>,
+    pub filename: String,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+}
+
+impl TryFrom<HashMap<String, String>> for PackageStanza {
+    type Error = Error;
+
+    fn try_from(stanza: HashMap<String, String>) -> Result<Self, Error> {
+        // ids get split into a two character directory and the rest, and
+        // the first 16 characters are read as hex for the qid; anything
+        // else is dropped.
+        let build_ids: Vec<String> = get(&stanza, "Build-Ids")
+            .ok_or(Error::Malformed)?
+            .split_whitespace()
+            .filter(|v| v.len() >= 16 && v.bytes().all(|b| b.is_ascii_hexdigit()))
+            .map(|v| v.to_lowercase())
+            .collect();
+        if build_ids.is_empty() {
+            return Err(Error::Malformed);
+        }
+
+        let filename = get(&stanza, "Filename").ok_or(Error::Malformed)?.clone();
+        let size = match get(&stanza, "Size") {
+            None => None,
+            Some(v) => Some(v.parse().map_err(|_| Error::Malformed)?),
+        };
+
+        Ok(PackageStanza {
+            package: get(&stanza, "Package").cloned(),
+            version: get(&stanza, "Version").cloned(),
+            build_ids,
+            filename,
+            sha256: get(&stanza, "SHA256").cloned(),
+            size,
+        })
+    }
+}
+
+/// Compare two Debian package versions the way dpkg does: by epoch, then
+/// the upstream version, then the Debian revision.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| {
+        let (epoch, rest) = match v.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse::<u64>().unwrap_or(0), rest),
+            None => (0, v),
+        };
+        let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+        (epoch, upstream.to_owned(), revision.to_owned())
+    };
+    let (a, b) = (split(a), split(b));
+    a.0.cmp(&b.0)
+        .then_with(|| part_cmp(&a.1, &b.1))
+        .then_with(|| part_cmp(&a.2, &b.2))
+}
+
+/// Compare one part of a version, alternating between runs of non-digits,
+/// where `~` sorts before anything (even the end) and letters before other
+/// characters, and runs of digits, compared as numbers.
+fn part_cmp(a: &str, b: &str) -> Ordering {
+    let order = |c: Option<u8>| -> i32 {
+        match c {
+            None => 0,
+            Some(b'~') => -1,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => c as i32,
+            Some(c) => c as i32 + 256,
+        }
+    };
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    while !a.is_empty() || !b.is_empty() {
+        while a.first().is_some_and(|c| !c.is_ascii_digit())
+            || b.first().is_some_and(|c| !c.is_ascii_digit())
+        {
+            let ord = order(a.first().copied()).cmp(&order(b.first().copied()));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = a.get(1..).unwrap_or(a);
+            b = b.get(1..).unwrap_or(b);
+        }
+
+        let digits = |v: &[u8]| v.iter().take_while(|c| c.is_ascii_digit()).count();
+        let (na, nb) = (digits(a), digits(b));
+        let num = |v: &[u8]| {
+            let v = std::str::from_utf8(v).unwrap_or("").trim_start_matches('0');
+            (v.len(), v.to_owned())
+        };
+        // compared as strings of the same length, so any number fits.
+        let ord = num(&a[..na]).cmp(&num(&b[..nb]));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+        a = &a[na..];
+        b = &b[nb..];
+    }
+    Ordering::Equal
+}
+
+/// Read the next stanza as a map from field name to value. If a field is
+/// repeated, the last one wins; use [fields] to see all of them.
+pub async fn next<T>(b: T) -> Result<Option<HashMap<String, String>>, Error>
 where
     T: Unpin,
     T: AsyncBufRead,
 {
-    let mut ret = HashMap::new();
+    Ok(fields(b).await?.map(|fields| fields.into_iter().collect()))
+}
+
+/// Look up `key` in a stanza from [next]. Field names are case-insensitive,
+/// so this falls back to a scan if the exact casing isn't there.
+pub fn get<'a>(stanza: &'a HashMap<String, String>, key: &str) -> Option<&'a String> {
+    stanza.get(key).or_else(|| {
+        stanza
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    })
+}
+
+/// Skip the rest of the current stanza, up to and including the blank line
+/// that ends it, so that parsing can pick up again after a malformed one.
+pub async fn skip<T>(mut b: T) -> Result<(), Error>
+where
+    T: Unpin,
+    T: AsyncBufRead,
+{
+    loop {
+        // as bytes, since what we're skipping may not even be UTF-8.
+        let mut line = vec![];
+        if b.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+    }
+}
+
+/// Read the next stanza as its fields, in the order they were written,
+/// keeping any that are repeated.
+pub async fn fields<T>(mut b: T) -> Result<Option<Vec<(String, String)>>, Error>
+where
+    T: Unpin,
+    T: AsyncBufRead,
+{
+    let mut ret: Vec<(String, String)> = vec![];
     loop {
         let mut line = String::new();
         let n = b.read_line(&mut line).await?;
@@ -49,16 +195,45 @@ where
             }
             break;
         }
+        if line.ends_with("\r\n") {
+            // mirrors hosted on Windows boxes send CRLF.
+            line.truncate(line.len() - 2);
+            line.push('\n');
+        }
+
+        if line.starts_with('#') {
+            // comments only start in the first column; anything indented
+            // is part of the field before it.
+            continue;
+        }
 
-        let line = line.trim();
-        if line == "" {
+        if line.trim() == "" {
+            if ret.is_empty() {
+                // extra blank lines, or a stanza that was all comments.
+                continue;
+            }
             break;
         }
-        let (key, value) = match line.split_once(":") {
+
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // continuation of the previous field, such as the checksum
+            // lists in a Release file.
+            let value = match ret.last_mut() {
+                None => return Err(Error::Malformed),
+                Some((_, v)) => v,
+            };
+            if !value.is_empty() {
+                value.push('\n');
+            }
+            value.push_str(line.trim());
+            continue;
+        }
+
+        let (key, value) = match line.trim().split_once(":") {
             None => return Err(Error::Malformed),
             Some(v) => v,
         };
-        ret.insert(key.trim().to_owned(), value.trim().to_owned());
+        ret.push((key.trim().to_owned(), value.trim().to_owned()));
     }
 
     Ok(Some(ret))
@@ -66,7 +241,8 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::next;
+    use super::{fields, get, next, version_cmp, PackageStanza};
+    use std::cmp::Ordering;
     use std::io::Cursor;
 
     #[tokio::test]
@@ -89,6 +265,226 @@ Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
 
         assert!(next(&mut cur).await.unwrap().is_none());
     }
+
+    #[tokio::test]
+    async fn folded() {
+        let packages = "Package: zzuf-dbgsym
+Description: debug symbols for zzuf
+ Multi-purpose fuzzer.
+ .
+\tzzuf is a transparent application input fuzzer.
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+";
+
+        let mut cur = Cursor::new(packages);
+        let zzuf = next(&mut cur).await.unwrap().unwrap();
+        assert_eq!(
+            "debug symbols for zzuf\nMulti-purpose fuzzer.\n.\nzzuf is a transparent application input fuzzer.",
+            zzuf["Description"]
+        );
+        assert_eq!(
+            "pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb",
+            zzuf["Filename"]
+        );
+
+        // nothing to fold a continuation into.
+        let mut cur = Cursor::new(" Multi-purpose fuzzer.\n");
+        assert!(next(&mut cur).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ordered() {
+        let stanza = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+Build-Ids: 49a0ba466e7cea361ccb59d054ba9986a1ab7824
+";
+
+        assert_eq!(
+            vec![
+                ("Package".to_owned(), "zzuf-dbgsym".to_owned()),
+                (
+                    "Build-Ids".to_owned(),
+                    "1c54e04fcf760c428d0afa79a33ffb8e068d35d5".to_owned()
+                ),
+                (
+                    "Filename".to_owned(),
+                    "pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb".to_owned()
+                ),
+                (
+                    "Build-Ids".to_owned(),
+                    "49a0ba466e7cea361ccb59d054ba9986a1ab7824".to_owned()
+                ),
+            ],
+            fields(&mut Cursor::new(stanza)).await.unwrap().unwrap()
+        );
+
+        let zzuf = next(&mut Cursor::new(stanza)).await.unwrap().unwrap();
+        assert_eq!(
+            "49a0ba466e7cea361ccb59d054ba9986a1ab7824",
+            zzuf["Build-Ids"]
+        );
+    }
+
+    #[tokio::test]
+    async fn case_insensitive() {
+        let stanza = "package: zzuf-dbgsym
+Build-IDs: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+";
+
+        let zzuf = next(&mut Cursor::new(stanza)).await.unwrap().unwrap();
+        assert_eq!(Some("zzuf-dbgsym"), get(&zzuf, "Package").map(|v| &v[..]));
+        assert_eq!(
+            Some("1c54e04fcf760c428d0afa79a33ffb8e068d35d5"),
+            get(&zzuf, "Build-Ids").map(|v| &v[..])
+        );
+        assert_eq!(None, get(&zzuf, "Filename"));
+    }
+
+    #[tokio::test]
+    async fn comments() {
+        let packages = "# generated by a mirror script
+
+# zzuf
+Package: zzuf-dbgsym
+# Build-Ids: 0000000000000000000000000000000000000000
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Description: debug symbols for zzuf
+ # not a comment
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+# trailing
+";
+
+        let mut cur = Cursor::new(packages);
+        let zzuf = next(&mut cur).await.unwrap().unwrap();
+        assert_eq!(4, zzuf.len());
+        assert_eq!("zzuf-dbgsym", zzuf["Package"]);
+        assert_eq!(
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            zzuf["Build-Ids"]
+        );
+        assert_eq!(
+            "debug symbols for zzuf\n# not a comment",
+            zzuf["Description"]
+        );
+        assert_eq!(
+            "pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb",
+            zzuf["Filename"]
+        );
+        assert!(next(&mut cur).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn crlf() {
+        let packages = "Package: zziplib-bin-dbgsym
+Build-Ids: 204d62991035324322317de6f71f494c06a10d37 23c08beddf41e0098035f3c34274450ccc0a9f21
+Filename: pool/main/z/zziplib/zziplib-bin-dbgsym_0.13.72+dfsg.1-1.2_amd64.deb
+SHA256:
+ e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855
+
+Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+";
+        let crlf = packages.replace('\n', "\r\n");
+
+        let mut lf = Cursor::new(packages);
+        let mut cur = Cursor::new(crlf);
+        for _ in 0..2 {
+            let stanza = fields(&mut cur).await.unwrap().unwrap();
+            assert!(stanza
+                .iter()
+                .all(|(k, v)| !k.contains('\r') && !v.contains('\r')));
+            assert_eq!(fields(&mut lf).await.unwrap().unwrap(), stanza);
+        }
+        assert!(fields(&mut cur).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn package_stanza() {
+        let packages = "Package: zzuf-dbgsym
+Version: 0.15-2+b4
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5 49A0BA466E7CEA361CCB59D054BA9986A1AB7824
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+Size: 1024
+
+Package: no-build-ids
+Filename: pool/main/n/no-build-ids.deb
+
+Package: no-filename
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+
+Package: short-build-id
+Build-Ids: 1c
+Filename: pool/main/s/short-build-id.deb
+
+Package: short-build-ids
+Build-Ids: 1c54e04f 49a0ba46
+Filename: pool/main/s/short-build-ids.deb
+
+Package: not-hex-build-id
+Build-Ids: zz54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/n/not-hex-build-id.deb
+
+Package: bad-size
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/b/bad-size.deb
+Size: big
+";
+
+        let mut cur = Cursor::new(packages);
+        let zzuf = PackageStanza::try_from(next(&mut cur).await.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            PackageStanza {
+                package: Some("zzuf-dbgsym".to_owned()),
+                version: Some("0.15-2+b4".to_owned()),
+                build_ids: vec![
+                    "1c54e04fcf760c428d0afa79a33ffb8e068d35d5".to_owned(),
+                    "49a0ba466e7cea361ccb59d054ba9986a1ab7824".to_owned(),
+                ],
+                filename: "pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb".to_owned(),
+                sha256: None,
+                size: Some(1024),
+            },
+            zzuf
+        );
+
+        let mixed = "Package: mixed
+Build-Ids: 1c54e04f zz54e04fcf760c428d0afa79a33ffb8e068d35d5 49a0ba466e7cea36
+Filename: pool/main/m/mixed.deb
+";
+        let mixed = next(&mut Cursor::new(mixed)).await.unwrap().unwrap();
+        assert_eq!(
+            vec!["49a0ba466e7cea36".to_owned()],
+            PackageStanza::try_from(mixed).unwrap().build_ids
+        );
+
+        while let Some(stanza) = next(&mut cur).await.unwrap() {
+            let package = stanza["Package"].clone();
+            assert!(PackageStanza::try_from(stanza).is_err(), "{package}");
+        }
+    }
+
+    #[test]
+    fn versions() {
+        for (a, b) in [
+            ("0.15-2", "0.15-2+b1"),
+            ("0.15-2+b1", "0.15-2+b10"),
+            ("0.15-2+b4", "0.15-3"),
+            ("1.0~rc1-1", "1.0-1"),
+            ("1.0-1", "1.0a-1"),
+            ("1.0a-1", "1.0+dfsg-1"),
+            ("9.9-1", "1:0.1-1"),
+            ("1.2.3", "1.10"),
+            ("1.0", "1.0-0.1"),
+            ("2.0-1", "18446744073709551616.0-1"),
+        ] {
+            assert_eq!(Ordering::Less, version_cmp(a, b), "{a} < {b}");
+            assert_eq!(Ordering::Greater, version_cmp(b, a), "{b} > {a}");
+        }
+        assert_eq!(Ordering::Equal, version_cmp("1.01-1", "1.1-1"));
+        assert_eq!(Ordering::Equal, version_cmp("0:1.0-1", "1.0-1"));
+    }
 }
 
 // vim: foldmethod=marker
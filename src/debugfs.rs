@@ -18,410 +18,4996 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE. }}}
 
-use super::{deb822, Deb, Decompress};
+use super::{
+    data_tar, deb822, elf, hrange, keyring, redact, source, Buffers, CachedFile, Deb, Debian,
+    Decompress, DiskCache, Layout, Mismatch, NotADeb, Options, Timeout, Truncated, TruncatedStream,
+    Unsupported, METRICS,
+};
 use arigato::{
     raw::{Dehydrate, FileType, IoDirection, OpenMode, Qid, Stat},
     server::{File as FileTrait, FileError, FileResult, Filesystem, OpenFile as OpenFileTrait},
 };
-use futures::TryFutureExt;
+use async_compression::tokio::bufread::GzipDecoder;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
-    io::{Cursor, Read, Seek, SeekFrom},
-    sync::Arc,
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{Cursor, ErrorKind},
+    os::unix::fs::FileExt,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
 };
-use tokio::io::AsyncReadExt;
 use tokio_stream::StreamExt;
-use tokio_tar::{Archive, Entry};
-use xz2::read::XzDecoder;
+use tokio_tar::Archive;
+use tracing::Instrument;
 use xz2::stream::Action;
 
-// ///
-// type JoinSet = tokio::task::JoinSet<()>;
+/// Qid path of the synthetic `.build-ids` file, well clear of the prefix
+/// directories.
+const BUILD_IDS_QID: u64 = u64::MAX - 1;
+
+/// Qid path of the synthetic `index.tsv` file, next to [BUILD_IDS_QID].
+const INDEX_QID: u64 = u64::MAX - 2;
+
+/// Qid paths of the per-architecture roots, `.build-ids` and `index.tsv`
+/// files, architecture and suite directories count down from here, eight
+/// to a namespace.
+const ARCH_QID: u64 = u64::MAX - 0x100;
+
+/// Each suite gets this many namespaces, one for every architecture.
+const SUITE_NS: u64 = 0x1000;
+
+/// How long a loaded index is served before an attach refreshes it.
+const DEFAULT_INDEX_TTL: Duration = Duration::from_secs(300);
+
+/// How many `.deb` fetches can be in flight at once, by default.
+const DEFAULT_MAX_FETCHES: usize = 8;
+
+/// Largest `Packages` file we'll hold in memory, decompressed, by default.
+const DEFAULT_MAX_INDEX_SIZE: u64 = 1 << 30;
+
+/// About how much of a decompressed `Packages` file is handed to each
+/// indexing task, while the rest is still coming in.
+const INDEX_CHUNK: usize = 4 << 20;
+
+/// Body of a file on the mirror, as it's read.
+type Body = Pin<Box<dyn AsyncRead + Send>>;
 
 ///
 pub(crate) struct Debug {
     archive_root: String,
-    // suite: String,
-    // component: String,
-    packages: String,
+    /// where the `Filename`s in the index are, if not under `archive_root`.
+    pool_root: Option<String>,
+    /// where the indices and debug files are in the archive.
+    layout: Arc<dyn Layout>,
+    suite: String,
+    components: Option<Vec<String>>,
+    arches: Option<Vec<String>>,
+    serve_stale: bool,
+    index_ttl: Duration,
+    cached: Arc<Mutex<Option<Cached>>>,
+    /// held while the index is being refreshed, so attaches that find it
+    /// out of date at the same time wait on the one refresh.
+    refreshing: Arc<tokio::sync::Mutex<()>>,
+    anames: Option<Vec<String>>,
+    default_aname: Option<String>,
+    fail_on_empty: bool,
+    sessions: Option<Arc<Semaphore>>,
+    cache: Option<Arc<DiskCache>>,
+    arch_dirs: bool,
+    /// first namespace for the trees of this suite.
+    ns: u64,
+    /// other suites served next to this one, if any.
+    suites: Vec<String>,
+    suite_dirs: Arc<OnceLock<Arc<Vec<File>>>>,
+    by_hash: bool,
+    keyring: Option<PathBuf>,
+    stat_sizes: bool,
+    strip: bool,
+    buffers: Buffers,
+    fetches: Option<Arc<Semaphore>>,
+    inflight: Inflight,
+    /// most bytes of any one file we fetch for the index, decompressed.
+    max_index_size: u64,
+    /// how we talk to the mirror.
+    http: Arc<Options>,
 }
 
-impl Debug {
-    ///
-    pub fn new(archive_root: &str, suite: &str, component: &str, arch: &str) -> Self {
-        Debug {
-            archive_root: archive_root.to_owned(),
-            // suite: suite.to_owned(),
-            // component: component.to_owned(),
-            packages: format!("{archive_root}/dists/{suite}/{component}/binary-{arch}/Packages.xz"),
-        }
-    }
+/// The most recently loaded index, reused by attaches until it is older
+/// than the TTL, and kept around after that so that it can still be served
+/// if the mirror goes away.
+#[derive(Clone)]
+struct Cached {
+    loaded: Instant,
+    /// when we last tried to refresh it, successfully or not.
+    attempted: Instant,
+    root: Root,
 }
 
-impl Filesystem for Debug {
-    type File = File;
-
-    async fn attach(&self, _: &str, _: &str, _: u32) -> FileResult<File> {
-        tracing::info!("requesting {}", &self.packages);
-        let client = reqwest::Client::new();
-        let response = client
-            .get(&self.packages)
-            .send()
-            .await
-            .map_err(|_| FileError(121, "EREMOTEIO".to_owned()))?;
-
-        if response.status() != 200 {
-            return Err(FileError(121, "EREMOTEIO".to_owned()));
-        }
+/// A single component/architecture pair within a suite, each of which has
+/// its own `Packages` index.
+#[derive(Debug, Clone, PartialEq)]
+struct Source {
+    component: String,
+    arch: String,
+}
 
-        let decompressor = XzDecoder::new(Cursor::new(
-            response
-                .bytes()
-                .await
-                .map_err(|_| FileError(121, "EREMOTEIO".to_owned()))?,
-        ));
-        let response_bytes: std::io::Result<Vec<u8>> = decompressor.bytes().collect();
-        let response_bytes = response_bytes.map_err(|_| FileError(121, "EREMOTEIO".to_owned()))?;
-        let mut body = Cursor::new(response_bytes);
+impl Source {
+    ///
+    fn packages(&self, layout: &dyn Layout, archive_root: &str, suite: &str, name: &str) -> String {
+        format!(
+            "{archive_root}/{}/{}/{name}",
+            layout.suite_dir(suite),
+            layout.index_dir(&self.component, &self.arch)
+        )
+    }
 
-        let mut entries = HashMap::<String, (String, Vec<File>)>::new();
-        loop {
-            let headers = match deb822::next(&mut body)
-                .await
-                .map_err(|_| FileError(121, "EREMOTEIO".to_owned()))?
-            {
-                None => {
-                    break;
-                }
-                Some(v) => v,
-            };
+    /// URL of our `Packages` file under its `SHA256` from `release`, which
+    /// stays put while a mirror push replaces the regular path. Like apt,
+    /// we look for `by-hash` next to the file itself.
+    fn packages_by_hash(
+        &self,
+        layout: &dyn Layout,
+        archive_root: &str,
+        suite: &str,
+        release: &HashMap<String, String>,
+    ) -> Option<String> {
+        let hash = self.sha256(layout, release, "Packages.xz")?;
+        Some(format!(
+            "{archive_root}/{}/{}/by-hash/SHA256/{hash}",
+            layout.suite_dir(suite),
+            layout.index_dir(&self.component, &self.arch)
+        ))
+    }
 
-            let build_ids = headers.get("Build-Ids");
-            if let None = build_ids {
-                // malformed
-                continue;
+    /// `SHA256` of our index file `name`, as listed in `release`.
+    fn sha256<'a>(
+        &self,
+        layout: &dyn Layout,
+        release: &'a HashMap<String, String>,
+        name: &str,
+    ) -> Option<&'a str> {
+        let path = format!("{}/{name}", layout.index_dir(&self.component, &self.arch));
+        deb822::get(release, "SHA256")?.lines().find_map(|line| {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [hash, _, name] if name == path => Some(hash),
+                _ => None,
             }
+        })
+    }
+}
 
-            let path = match headers.get("Filename") {
-                None => {
-                    // malformed
-                    continue;
-                }
-                Some(v) => v,
-            };
-
-            for build_id in build_ids.unwrap().split(" ") {
-                let dir_name = build_id[..2].to_owned();
-                let (_, dir_entries) = entries
-                    .entry(dir_name.clone())
-                    .or_insert((dir_name.clone(), vec![]));
-                dir_entries.push(File::DebugHeader(DebugHeader {
-                    fspath: format!("{}/{}.debug", dir_name, &build_id[2..]),
-                    build_id: build_id.to_owned(),
-                    name: format!("{}.debug", &build_id[2..]),
-                    pool: format!("{}/{}", self.archive_root, path),
-                }));
-            }
-        }
+/// Build the list of component/arch pairs to serve. Anything not given
+/// explicitly is taken from the suite's `Release` file; the `all`
+/// pseudo-architecture never carries debug symbols, so it is dropped.
+fn sources(
+    release: Option<&HashMap<String, String>>,
+    components: Option<&[String]>,
+    arches: Option<&[String]>,
+) -> Vec<Source> {
+    let field = |name: &str| -> Vec<String> {
+        release
+            .and_then(|release| deb822::get(release, name))
+            .map(|v| v.split_whitespace().map(|v| v.to_owned()).collect())
+            .unwrap_or_default()
+    };
 
-        // tokio spawn on a joinset
+    let components = match components {
+        Some(v) => v.to_vec(),
+        None => field("Components"),
+    };
+    let arches = match arches {
+        Some(v) => v.to_vec(),
+        None => field("Architectures")
+            .into_iter()
+            .filter(|arch| arch != "all")
+            .collect(),
+    };
 
-        Ok(File::Root(Root {
-            // join_set: Arc::new(JoinSet::new()),
-            directory: Arc::new(Box::new(Directory {
-                name: "/".to_owned(),
-                entries: Arc::new(
-                    entries
-                        .into_iter()
-                        .map(|(_, (name, entries))| {
-                            File::Directory(Directory {
-                                name,
-                                entries: Arc::new(entries),
-                            })
-                        })
-                        .collect(),
-                ),
-            })),
-        }))
-    }
+    components
+        .iter()
+        .flat_map(|component| {
+            arches.iter().map(|arch| Source {
+                component: component.clone(),
+                arch: arch.clone(),
+            })
+        })
+        .collect()
 }
 
-///
-#[derive(Debug, Clone)]
-pub(crate) struct Directory {
-    name: String,
-    entries: Arc<Vec<File>>,
+/// Decompress a `Packages` file as it's read, going by the extension on
+/// `name`.
+async fn decompress_packages(name: &str, body: Body, buffers: Buffers) -> FileResult<Body> {
+    let packages: Body = match name {
+        name if name.ends_with(".xz") => Box::pin(
+            Decompress::xz(body, buffers)
+                .await
+                .map_err(|_| FileError(5, "EIO".to_owned()))?,
+        ),
+        name if name.ends_with(".gz") => Box::pin(GzipDecoder::new(BufReader::new(body))),
+        _ => body,
+    };
+    Ok(packages)
 }
 
-impl Directory {
-    ///
-    async fn open_dir(&self, om: OpenMode) -> FileResult<OpenFile> {
-        match om.direction() {
-            IoDirection::Read => {}
-            _ => return Err(FileError(1, "EPERM".to_owned())),
-        }
+/// Reader that feeds everything read through it into `sha256`, so that
+/// it can be checked once the reading is done.
+#[pin_project::pin_project]
+struct Hashing<R> {
+    #[pin]
+    inner: R,
+    sha256: Arc<Mutex<Sha256>>,
+}
 
-        let mut ent = Cursor::new(vec![]);
-        for dirent in self.entries.iter() {
-            let stat = dirent.stat().await?;
-            match stat.dehydrate(&mut ent) {
-                Ok(_) => {}
-                Err(_) => return Err(FileError(22, "EINVAL".to_owned())),
-            }
+impl<R: AsyncRead> AsyncRead for Hashing<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let read = this.inner.poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = read {
+            this.sha256.lock().unwrap().update(&buf.filled()[filled..]);
         }
-        Ok(OpenFile::Cursor(ent))
+        read
     }
 }
 
-///
-#[derive(Debug, Clone)]
-pub(crate) struct Root {
-    directory: Arc<Box<Directory>>,
-    // join_set: Arc<JoinSet>,
+/// Fail with `EFBIG` if `size` bytes of `what` is over `limit`.
+fn check_size(what: &str, size: u64, limit: u64) -> FileResult<()> {
+    if size > limit {
+        tracing::warn!(
+            "{} is bigger than the {} byte index limit",
+            redact(what),
+            limit
+        );
+        return Err(FileError(27, "EFBIG".to_owned()));
+    }
+    Ok(())
 }
 
-///
-#[derive(Debug, Clone)]
-pub(crate) struct DebugHeader {
-    name: String,
-    build_id: String,
-    pool: String,
-    fspath: String,
+/// GET `url`, failing with `ENOENT` if it isn't on the mirror, or with
+/// `EFBIG` once it's more than `limit` bytes. `file://` URLs are read
+/// straight off the local filesystem.
+async fn fetch(url: &str, limit: u64, http: &Options) -> FileResult<Vec<u8>> {
+    let mut body = vec![];
+    fetch_body(url, limit, http)
+        .await?
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut body)
+        .await
+        .map_err(|_| read_error(url))?;
+    check_size(url, body.len() as u64, limit)?;
+    Ok(body)
 }
 
-///
-#[derive(Debug, Clone)]
-pub(crate) enum File {
-    ///
-    Root(Root),
+/// Start a GET of `url`, handing back the body to be read as it comes in.
+/// Fails with `ENOENT` if it isn't on the mirror, or with `EFBIG` if the
+/// mirror says up front that it's more than `limit` bytes.
+async fn fetch_body(url: &str, limit: u64, http: &Options) -> FileResult<Body> {
+    if let Some(path) = source::local_path(url) {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| match err.kind() {
+                ErrorKind::NotFound => FileError(2, "ENOENT".to_owned()),
+                _ => FileError(5, "EIO".to_owned()),
+            })?;
+        let len = file
+            .metadata()
+            .await
+            .map_err(|_| FileError(5, "EIO".to_owned()))?
+            .len();
+        check_size(url, len, limit)?;
+        return Ok(Box::pin(file));
+    }
 
-    ///
-    Directory(Directory),
+    let response = hrange::get(url, http.clone()).await.map_err(|err| {
+        tracing::warn!("failed to fetch {}: {}", redact(url), err);
+        FileError(121, "EREMOTEIO".to_owned())
+    })?;
 
-    ///
-    DebugHeader(DebugHeader),
+    if response.status == 404 {
+        return Err(FileError(2, "ENOENT".to_owned()));
+    }
+    if response.status != 200 {
+        return Err(FileError(121, "EREMOTEIO".to_owned()));
+    }
+    if let Some(len) = response.content_length {
+        check_size(url, len, limit)?;
+    }
+    Ok(response.body)
 }
 
-pub(crate) enum OpenFile {
-    ///
-    Cursor(Cursor<Vec<u8>>),
+/// What a failed read of a body from [fetch_body] turns into: the local
+/// disk's problem for `file://` URLs, the mirror's otherwise.
+fn read_error(url: &str) -> FileError {
+    match source::local_path(url) {
+        Some(_) => FileError(5, "EIO".to_owned()),
+        None => FileError(121, "EREMOTEIO".to_owned()),
+    }
+}
 
+impl Debug {
     ///
-    DebEntry(DebEntry),
-}
+    pub fn new(archive_root: &str, suite: &str, component: &str, arch: &str) -> Self {
+        Self::discover(archive_root, suite)
+            .with_components(&[component])
+            .with_arches(&[arch])
+    }
 
-struct DebEntry {
-    offset: u64,
-    file: Entry<Archive<Decompress>>,
-}
+    /// Serve every component and architecture listed in the suite's
+    /// `Release` file.
+    pub fn discover(archive_root: &str, suite: &str) -> Self {
+        Debug {
+            archive_root: archive_root.to_owned(),
+            pool_root: None,
+            layout: Arc::new(Debian),
+            suite: suite.to_owned(),
+            components: None,
+            arches: None,
+            serve_stale: true,
+            index_ttl: DEFAULT_INDEX_TTL,
+            cached: Arc::default(),
+            refreshing: Arc::default(),
+            anames: None,
+            default_aname: None,
+            fail_on_empty: false,
+            sessions: None,
+            cache: None,
+            arch_dirs: false,
+            ns: 0,
+            suites: vec![],
+            suite_dirs: Arc::default(),
+            by_hash: false,
+            keyring: None,
+            stat_sizes: false,
+            strip: false,
+            buffers: Buffers::default(),
+            fetches: Some(Arc::new(Semaphore::new(DEFAULT_MAX_FETCHES))),
+            inflight: Inflight::default(),
+            max_index_size: DEFAULT_MAX_INDEX_SIZE,
+            http: Arc::default(),
+        }
+    }
 
-impl DebEntry {
-    async fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<u64> {
-        if off != self.offset {
-            return Err(FileError(29, "ESPIPE".to_owned()));
+    /// Only trust indices from an `InRelease` file signed by a key in
+    /// `keyring`, and check every `Packages` file against the hashes it
+    /// lists. Attaches fail if any of that doesn't check out.
+    pub fn with_keyring(mut self, keyring: &Path) -> Self {
+        self.keyring = Some(keyring.to_owned());
+        self
+    }
+
+    /// Find the indices and debug files in the archive with `layout`,
+    /// rather than where a Debian archive has them.
+    pub fn with_layout(mut self, layout: Arc<dyn Layout>) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Fetch each `Packages` file through `by-hash`, using the hashes in
+    /// the suite's `Release` file, so that a mirror push can't swap it out
+    /// from under us.
+    pub fn with_by_hash(mut self, by_hash: bool) -> Self {
+        self.by_hash = by_hash;
+        self
+    }
+
+    /// Serve `suites` as well as the one this was created with, each as a
+    /// top-level directory named after the suite. Each suite loads its own
+    /// index the first time something walks into it.
+    pub fn with_suites(mut self, suites: &[&str]) -> Self {
+        self.suites = suites.iter().map(|v| (*v).to_owned()).collect();
+        self
+    }
+
+    /// Directories for every suite we serve, sharing our configuration.
+    fn suite_dirs(&self) -> Arc<Vec<File>> {
+        self.suite_dirs
+            .get_or_init(|| {
+                let suites = std::iter::once(&self.suite).chain(self.suites.iter());
+                Arc::new(
+                    suites
+                        .enumerate()
+                        .map(|(idx, suite)| {
+                            File::Suite(Suite {
+                                name: suite.clone(),
+                                debug: Arc::new(self.for_suite(suite, (idx as u64 + 1) * SUITE_NS)),
+                            })
+                        })
+                        .collect(),
+                )
+            })
+            .clone()
+    }
+
+    /// Copy of our configuration for serving `suite` under the namespaces
+    /// starting at `ns`. Sessions and anames are checked on attach, so
+    /// they're left out.
+    fn for_suite(&self, suite: &str, ns: u64) -> Self {
+        Debug {
+            archive_root: self.archive_root.clone(),
+            pool_root: self.pool_root.clone(),
+            layout: self.layout.clone(),
+            suite: suite.to_owned(),
+            components: self.components.clone(),
+            arches: self.arches.clone(),
+            serve_stale: self.serve_stale,
+            index_ttl: self.index_ttl,
+            cached: Arc::default(),
+            refreshing: Arc::default(),
+            anames: None,
+            default_aname: None,
+            fail_on_empty: self.fail_on_empty,
+            sessions: None,
+            cache: self.cache.clone(),
+            arch_dirs: self.arch_dirs,
+            ns,
+            suites: vec![],
+            suite_dirs: Arc::default(),
+            by_hash: self.by_hash,
+            keyring: self.keyring.clone(),
+            stat_sizes: self.stat_sizes,
+            strip: self.strip,
+            buffers: self.buffers,
+            fetches: self.fetches.clone(),
+            inflight: self.inflight.clone(),
+            max_index_size: self.max_index_size,
+            http: self.http.clone(),
         }
-        let n = self
-            .file
-            .read(buf)
-            .await
-            .map_err(|_| FileError(5, "EIO".to_owned()))? as u64;
-        self.offset += n;
-        Ok(n)
     }
-}
 
-impl DebugHeader {
-    async fn open_file(&self, om: OpenMode) -> FileResult<OpenFile> {
-        match om.direction() {
-            IoDirection::Read => {}
-            _ => return Err(FileError(1, "EPERM".to_owned())),
+    /// Another handle on this filesystem, sharing its index, cache and
+    /// fetch limit, for serving it some way other than 9p or for warming
+    /// the cache with [Self::prefetch] in the background.
+    pub fn handle(&self) -> Self {
+        Debug {
+            suites: self.suites.clone(),
+            cached: self.cached.clone(),
+            suite_dirs: self.suite_dirs.clone(),
+            ..self.for_suite(&self.suite, self.ns)
         }
+    }
 
-        tracing::debug!("opening deb: {}", self.pool);
-        let mut deb = Deb::open(&self.pool)
-            .await
-            .map_err(|_| FileError(5, "EIO".to_owned()))?;
+    /// The top of the filesystem: the index, or a directory for each suite
+    /// if we serve more than one.
+    async fn top(&self) -> FileResult<Root> {
+        Ok(match self.suites.is_empty() {
+            true => self.current().await?,
+            false => Self::container(self.suite_dirs().to_vec(), 0),
+        })
+    }
 
-        loop {
-            let entry = match deb
-                .next()
-                .await
-                .map_err(|_| FileError(5, "EIO".to_owned()))?
-            {
-                None => return Err(FileError(5, "EIO".to_owned())),
-                Some(v) => v,
-            };
-            tracing::debug!("loaded entry {:?}", entry.header());
+    /// The debug file for `build_id`, out of the first of our trees that
+    /// has it.
+    pub(crate) async fn lookup(&self, build_id: &str) -> FileResult<Option<DebugHeader>> {
+        if build_id.len() < 3 || !build_id.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let build_id = build_id.to_ascii_lowercase();
+        let name = format!("{}.debug", &build_id[2..]);
 
-            if entry.header().identifier == "data.tar.xz" {
-                let mut decoder = xz2::stream::Stream::new_stream_decoder(u64::MAX, 0).unwrap();
-                let mut body = entry.into_body();
-                let mut ar = Archive::new(
-                    Decompress::new(body)
-                        .await
-                        .map_err(|_| FileError(5, "EIO".to_owned()))?,
-                );
-                tracing::debug!("stream decompressing");
-
-                let mut entries = ar.entries().map_err(|_| FileError(5, "EIO".to_owned()))?;
-                while let Some(file) = entries.next().await {
-                    let mut file = file.map_err(|_| FileError(5, "EIO".to_owned()))?;
-                    tracing::debug!("found file {:?}", file.path());
-
-                    if file
-                        .path()
-                        .map_err(|_| FileError(5, "EIO".to_owned()))?
-                        .as_os_str()
-                        .to_str()
-                        .unwrap()
-                        == format!("./usr/lib/debug/.build-id/{}", self.fspath)
-                    {
-                        let mut header = Vec::new();
-                        file.read_to_end(&mut header)
-                            .map_err(|_| FileError(5, "EIO".to_owned()))
-                            .await?;
-
-                        return Ok(OpenFile::Cursor(Cursor::new(header)));
-
-                        // return Ok(OpenFile::DebEntry(DebEntry { offset: 0, file }));
+        let mut stack = vec![File::Root(self.top().await?)];
+        while let Some(file) = stack.pop() {
+            match file {
+                File::Root(root) if root.is_container() => {
+                    stack.extend(root.directory.entries.iter().rev().cloned())
+                }
+                File::Root(root) => {
+                    let Some(File::Directory(dir)) = root.directory.lookup(&build_id[..2]) else {
+                        continue;
+                    };
+                    if let Some(File::DebugHeader(header)) = dir.lookup(&name) {
+                        return Ok(Some(header.clone()));
                     }
                 }
+                File::Arch(arch) => stack.push(File::Root(arch.root)),
+                File::Suite(suite) => stack.extend(suite.entries().await?.into_iter().rev()),
+                _ => {}
             }
         }
+        Ok(None)
     }
-}
 
-impl File {
-    fn name(&self) -> &str {
-        match self {
-            Self::Root(_) => "/",
-            Self::Directory(dir) => &dir.name,
-            Self::DebugHeader(dbg) => &dbg.name,
+    /// Extract the debug file for every build-id in `wanted`, along with
+    /// every build-id of any package named in it, into the cache, so that
+    /// they're served off disk from the first open. Anything that fails is
+    /// logged and skipped. Returns how many are cached.
+    pub async fn prefetch(&self, wanted: &[String]) -> FileResult<usize> {
+        if self.cache.is_none() {
+            return Err(FileError(22, "EINVAL".to_owned()));
         }
-    }
+        let wanted: HashSet<&str> = wanted.iter().map(|v| v.as_str()).collect();
 
-    async fn walk_to(&self, path: &str) -> FileResult<Self> {
-        match self {
-            Self::Root(root) => {
-                for entry in root.directory.entries.iter() {
-                    if entry.name() == path {
-                        return Ok(entry.clone());
+        let root = self.top().await?;
+        let mut headers = vec![];
+        let mut packages = HashMap::new();
+        let mut stack = vec![File::Root(root)];
+        while let Some(file) = stack.pop() {
+            match file {
+                File::Root(root) => stack.extend(root.directory.entries.iter().cloned()),
+                File::Directory(directory) => stack.extend(directory.entries.iter().cloned()),
+                File::Arch(arch) => stack.push(File::Root(arch.root)),
+                File::Suite(suite) => stack.extend(suite.entries().await?),
+                File::DebugHeader(header) => headers.push(header),
+                File::Package(package) => {
+                    if let Some(name) = package.package {
+                        packages.insert(package.build_id, name);
                     }
                 }
+                File::PoolDeb(_) | File::BuildIds(_) | File::Index(_) => {}
             }
-            Self::Directory(dir) => {
-                for entry in dir.entries.iter() {
-                    if entry.name() == path {
-                        return Ok(entry.clone());
-                    }
+        }
+        headers.retain(|header| {
+            wanted.contains(header.build_id.as_str())
+                || packages
+                    .get(&header.build_id)
+                    .is_some_and(|name| wanted.contains(name.as_str()))
+        });
+        let total = headers.len();
+        tracing::info!("prefetching {} debug files for {}", total, self.suite);
+
+        let mut join_set = JoinSet::new();
+        for header in headers {
+            join_set.spawn(async move {
+                let res = header.open_cached().await.map(drop);
+                (header.build_id, res)
+            });
+        }
+        let (mut done, mut cached) = (0, 0);
+        while let Some(res) = join_set.join_next().await {
+            let (build_id, res) = res.map_err(|_| FileError(5, "EIO".to_owned()))?;
+            done += 1;
+            match res {
+                Ok(()) => {
+                    cached += 1;
+                    tracing::info!("prefetched {} ({}/{})", build_id, done, total);
+                }
+                Err(FileError(_, name)) => {
+                    tracing::warn!(
+                        "failed to prefetch {}: {} ({}/{})",
+                        build_id,
+                        name,
+                        done,
+                        total
+                    )
                 }
             }
-            _ => {}
+        }
+        Ok(cached)
+    }
+
+    /// Limit the number of clients attached at once; attaches past the
+    /// limit fail with `EAGAIN` until an existing session ends.
+    pub fn with_max_sessions(mut self, sessions: usize) -> Self {
+        self.sessions = Some(Arc::new(Semaphore::new(sessions)));
+        self
+    }
+
+    ///
+    fn session(&self) -> FileResult<Option<Arc<OwnedSemaphorePermit>>> {
+        let sessions = match &self.sessions {
+            None => return Ok(None),
+            Some(v) => v.clone(),
         };
-        Err(FileError(2, "ENOENT".to_owned()))
+        match sessions.try_acquire_owned() {
+            Ok(permit) => Ok(Some(Arc::new(permit))),
+            Err(_) => {
+                tracing::warn!("too many sessions attached to {}", self.suite);
+                Err(FileError(11, "EAGAIN".to_owned()))
+            }
+        }
     }
-}
 
-impl FileTrait for File {
-    type OpenFile = OpenFile;
+    /// Fail the attach if the index didn't contain a single build-id,
+    /// rather than serving an empty tree.
+    pub fn with_fail_on_empty_index(mut self, fail_on_empty: bool) -> Self {
+        self.fail_on_empty = fail_on_empty;
+        self
+    }
 
-    async fn stat(&self) -> FileResult<Stat> {
-        let qid = self.qid();
+    /// Only allow clients to attach using one of the provided anames.
+    pub fn with_anames(mut self, anames: &[&str]) -> Self {
+        self.anames = Some(anames.iter().map(|v| (*v).to_owned()).collect());
+        self
+    }
 
-        let sb = Stat::builder(self.name(), qid)
-            .with_nuid(0)
-            .with_ngid(0)
-            .with_nmuid(0)
-            .with_size(1_000_000_000);
+    /// aname to use when a client attaches without providing one.
+    pub fn with_default_aname(mut self, aname: &str) -> Self {
+        self.default_aname = Some(aname.to_owned());
+        self
+    }
 
-        let sb = match self {
-            Self::Root(_) => sb.with_mode(0o555),
-            Self::Directory(_) => sb.with_mode(0o555),
-            Self::DebugHeader(_) => sb.with_mode(0o444),
+    /// Reject attaches to anames that aren't on the allowlist, if there is
+    /// one.
+    fn check_aname(&self, aname: &str) -> FileResult<()> {
+        let aname = match (aname, &self.default_aname) {
+            ("", Some(default)) => default.as_str(),
+            (aname, _) => aname,
+        };
+
+        let anames = match &self.anames {
+            None => return Ok(()),
+            Some(v) => v,
         };
 
-        Ok(sb.build())
+        if aname.is_empty() {
+            return Err(FileError(2, "ENOENT".to_owned()));
+        }
+        if !anames.iter().any(|v| v == aname) {
+            tracing::warn!("rejecting attach to {:?}", aname);
+            return Err(FileError(13, "EACCES".to_owned()));
+        }
+        Ok(())
     }
 
-    async fn wstat(&mut self, _: &Stat) -> FileResult<()> {
-        Err(FileError(1, "EPERM".to_owned()))
+    /// Fetch `.deb` files from under `pool_root` rather than the archive
+    /// root, for mirrors that keep `pool/` on another host than `dists/`.
+    pub fn with_pool_root(mut self, pool_root: &str) -> Self {
+        self.pool_root = Some(pool_root.to_owned());
+        self
     }
 
-    async fn walk(&self, path: &[&str]) -> FileResult<(Option<Self>, Vec<Self>)> {
-        if path.is_empty() {
-            return Ok((Some(self.clone()), vec![]));
-        }
+    /// Keep serving the last index that loaded if refreshing it fails,
+    /// rather than failing the attach. Enabled by default.
+    pub fn with_serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
 
-        let mut my_path = self.clone();
-        let mut walked_path = vec![];
-        for part in path {
-            my_path = match self.walk_to(part).await {
-                Ok(v) => {
-                    walked_path.push(my_path);
-                    v
-                }
-                Err(_) => return Ok((None, walked_path)),
-            };
-        }
+    /// Reuse a loaded index for attaches within `ttl` of loading it, rather
+    /// than fetching every `Packages` file again. Defaults to five minutes;
+    /// zero refreshes on every attach.
+    pub fn with_index_ttl(mut self, ttl: Duration) -> Self {
+        self.index_ttl = ttl;
+        self
+    }
 
-        Ok((Some(my_path), walked_path))
+    /// Keep extracted debug files in `cache`, and serve them from there
+    /// rather than fetching the `.deb` again. The cache can be shared
+    /// between filesystems.
+    pub fn with_cache(mut self, cache: Arc<DiskCache>) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
-    async fn unlink(&mut self) -> FileResult<()> {
-        Err(FileError(1, "EPERM".to_owned()))
+    /// Find out the size of a debug file the first time it's stat'd, by
+    /// reading the `.deb` up to its tar header, rather than reporting 0
+    /// until it's been read. With a cache, the file is cached on the way.
+    pub fn with_stat_sizes(mut self, stat_sizes: bool) -> Self {
+        self.stat_sizes = stat_sizes;
+        self
     }
 
-    async fn create(
-        &mut self,
-        _: &str,
-        _: u16,
-        _: arigato::raw::FileType,
-        _: OpenMode,
-        _: &str,
-    ) -> FileResult<Self> {
-        Err(FileError(1, "EPERM".to_owned()))
+    /// Serve debug files with only the sections a debugger reads out of a
+    /// separate debug file, rather than all of them. The whole debug file
+    /// is read into memory to do this, rather than being streamed.
+    pub fn with_strip(mut self, strip: bool) -> Self {
+        self.strip = strip;
+        self
     }
 
-    async fn open(&mut self, om: OpenMode) -> FileResult<OpenFile> {
-        match self {
-            Self::Directory(dir) => dir.open_dir(om).await,
-            Self::Root(root) => root.directory.open_dir(om).await,
-            Self::DebugHeader(dh) => dh.open_file(om).await,
-        }
+    /// Size the buffers for decompressing `data.tar` members with
+    /// `buffers`, rather than the defaults.
+    pub fn with_buffers(mut self, buffers: Buffers) -> Self {
+        self.buffers = buffers;
+        self
     }
 
-    fn qid(&self) -> Qid {
-        match self {
-            Self::Root(_) => Qid::new(FileType::Dir, 0x01, 0x01),
-            Self::Directory(dir) => {
-                let id = u64::from_str_radix(&dir.name, 16).unwrap();
-                Qid::new(FileType::Dir, 0x01, id)
-            }
-            Self::DebugHeader(dh) => {
-                let id = u64::from_str_radix(&dh.build_id[..16], 16).unwrap();
-                Qid::new(FileType::File, 0x01, id)
-            }
-        }
+    /// Limit how many `.deb` files are fetched from the mirror at once, to
+    /// `fetches`; opens past the limit wait their turn. Defaults to 8, and
+    /// 0 lifts the limit. Debug files served from the cache don't count.
+    pub fn with_max_fetches(mut self, fetches: usize) -> Self {
+        self.fetches = match fetches {
+            0 => None,
+            n => Some(Arc::new(Semaphore::new(n))),
+        };
+        self
     }
-}
 
-impl OpenFileTrait for OpenFile {
-    fn iounit(&self) -> u32 {
-        0
+    /// Refuse to load a `Packages` file that's more than `size` bytes once
+    /// it's decompressed, rather than holding however much the mirror
+    /// sends in memory. Defaults to 1 GiB, and 0 lifts the limit.
+    pub fn with_max_index_size(mut self, size: u64) -> Self {
+        self.max_index_size = match size {
+            0 => u64::MAX,
+            n => n,
+        };
+        self
     }
 
-    async fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<u32> {
-        match self {
-            Self::DebEntry(file) => Ok(file.read_at(buf, off).await?.try_into().unwrap()),
-            Self::Cursor(cur) => {
-                cur.seek(SeekFrom::Start(off))?;
-                Ok(std::io::Read::read(cur, buf)?.try_into().unwrap())
-            }
-        }
+    /// Talk to the mirror with `options`, for the indices and the `.deb`
+    /// files both.
+    pub fn with_http_options(mut self, options: Options) -> Self {
+        self.http = Arc::new(options);
+        self
     }
 
-    async fn write_at(&mut self, _buf: &mut [u8], _off: u64) -> FileResult<u32> {
-        Err(FileError(1, "EPERM".to_owned()))
+    /// Serve each architecture as its own top-level directory, as
+    /// `amd64/.build-id/...`, rather than merging them into one tree.
+    pub fn with_arch_dirs(mut self, arch_dirs: bool) -> Self {
+        self.arch_dirs = arch_dirs;
+        self
+    }
+
+    /// Only serve the provided components, rather than those in `Release`.
+    pub fn with_components(mut self, components: &[&str]) -> Self {
+        self.components = Some(components.iter().map(|v| (*v).to_owned()).collect());
+        self
+    }
+
+    /// Only serve the provided architectures, rather than those in `Release`.
+    pub fn with_arches(mut self, arches: &[&str]) -> Self {
+        self.arches = Some(arches.iter().map(|v| (*v).to_owned()).collect());
+        self
+    }
+
+    /// `Packages.xz` for each component and architecture we were told to
+    /// serve. Any that are only listed in `Release` aren't known until the
+    /// index loads, so they're left out.
+    pub fn packages_urls(&self) -> Vec<String> {
+        sources(None, self.components.as_deref(), self.arches.as_deref())
+            .iter()
+            .map(|source| {
+                source.packages(
+                    &*self.layout,
+                    &self.archive_root,
+                    &self.suite,
+                    "Packages.xz",
+                )
+            })
+            .collect()
+    }
+
+    ///
+    async fn release(&self) -> FileResult<Option<HashMap<String, String>>> {
+        if let Some(keyring) = &self.keyring {
+            let url = format!(
+                "{}/{}/InRelease",
+                self.archive_root,
+                self.layout.suite_dir(&self.suite)
+            );
+            tracing::info!("requesting {}", redact(&url));
+            let signed = fetch(&url, self.max_index_size, &self.http).await?;
+            let release = keyring::verify(keyring, &signed).map_err(|err| {
+                tracing::warn!("bad signature on {}: {:?}", redact(&url), err);
+                FileError(5, "EIO".to_owned())
+            })?;
+            return deb822::next(&mut Cursor::new(release))
+                .await
+                .map_err(|_| FileError(121, "EREMOTEIO".to_owned()));
+        }
+
+        match (&self.components, &self.arches) {
+            (Some(_), Some(_)) if !self.by_hash => Ok(None),
+            _ => {
+                let url = format!(
+                    "{}/{}/Release",
+                    self.archive_root,
+                    self.layout.suite_dir(&self.suite)
+                );
+                tracing::info!("requesting {}", redact(&url));
+                let release = fetch(&url, self.max_index_size, &self.http).await?;
+                deb822::next(&mut Cursor::new(release))
+                    .await
+                    .map_err(|_| FileError(121, "EREMOTEIO".to_owned()))
+            }
+        }
+    }
+
+    /// Start fetching the `Packages` file for `source`, through `by-hash` if
+    /// we're asked to, otherwise trying `.xz`, then `.gz`, then the
+    /// uncompressed file, since not every mirror publishes all three. Hands
+    /// back which one it was, along with its (compressed) body.
+    async fn fetch_packages(
+        &self,
+        source: &Source,
+        release: Option<&HashMap<String, String>>,
+    ) -> FileResult<(&'static str, String, Body)> {
+        if self.by_hash {
+            let by_hash = release.and_then(|release| {
+                source.packages_by_hash(&*self.layout, &self.archive_root, &self.suite, release)
+            });
+            match by_hash {
+                Some(url) => {
+                    tracing::info!("requesting {}", redact(&url));
+                    let body = fetch_body(&url, self.max_index_size, &self.http).await?;
+                    return Ok(("Packages.xz", url, body));
+                }
+                None => tracing::warn!(
+                    "no SHA256 for {}/binary-{} in Release, using the regular path",
+                    source.component,
+                    source.arch
+                ),
+            }
+        }
+
+        for name in ["Packages.xz", "Packages.gz", "Packages"] {
+            let url = source.packages(&*self.layout, &self.archive_root, &self.suite, name);
+            tracing::info!("requesting {}", redact(&url));
+            let body = match fetch_body(&url, self.max_index_size, &self.http).await {
+                Err(FileError(2, _)) => continue,
+                other => other?,
+            };
+            tracing::info!(
+                "using {} for {}/binary-{}",
+                name,
+                source.component,
+                source.arch
+            );
+            return Ok((name, url, body));
+        }
+        Err(FileError(2, "ENOENT".to_owned()))
+    }
+
+    /// Fetch the `Packages` file for `source` and add every build-id it
+    /// lists to `entries`, decompressing and parsing it as it comes in.
+    /// Nothing is added unless the whole file checks out against
+    /// `release`. Returns how many stanzas it held.
+    async fn index_packages(
+        &self,
+        source: &Source,
+        release: Option<&HashMap<String, String>>,
+        entries: &mut HashMap<String, (String, Vec<File>)>,
+    ) -> FileResult<Stanzas> {
+        let (name, url, body) = self.fetch_packages(source, release).await?;
+        let sha256 = Arc::new(Mutex::new(Sha256::new()));
+        let body = Box::pin(Hashing {
+            inner: body,
+            sha256: sha256.clone(),
+        });
+        let packages = decompress_packages(name, body, self.buffers).await?;
+
+        let mut indexed = HashMap::new();
+        let stanzas = self
+            .index_reader(&url, packages, &mut indexed, workers(), INDEX_CHUNK)
+            .await?;
+
+        let actual = format!("{:x}", sha256.lock().unwrap().clone().finalize());
+        self.check_packages(source, release, name, &actual)?;
+        merge(entries, indexed);
+        Ok(stanzas)
+    }
+
+    /// If we have a keyring, make sure `actual`, the SHA256 of what we
+    /// fetched, is that of the `name` index the signed `Release` file lists
+    /// for `source`.
+    fn check_packages(
+        &self,
+        source: &Source,
+        release: Option<&HashMap<String, String>>,
+        name: &str,
+        actual: &str,
+    ) -> FileResult<()> {
+        if self.keyring.is_none() {
+            return Ok(());
+        }
+        let expected = release.and_then(|release| source.sha256(&*self.layout, release, name));
+        if expected != Some(actual) {
+            tracing::warn!(
+                "{}/binary-{}/{} doesn't match Release: expected {:?}, got {}",
+                source.component,
+                source.arch,
+                name,
+                expected,
+                actual
+            );
+            return Err(FileError(5, "EIO".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Fetch and parse every `Packages` file for this suite.
+    async fn load(&self) -> FileResult<Root> {
+        let release = self.release().await?;
+        let sources = sources(
+            release.as_ref(),
+            self.components.as_deref(),
+            self.arches.as_deref(),
+        );
+
+        let mut arches = BTreeMap::<String, HashMap<String, (String, Vec<File>)>>::new();
+        let mut with_build_ids = 0;
+        for source in sources {
+            let span = tracing::info_span!(
+                "index",
+                packages = %redact(&source.packages(
+                    &*self.layout,
+                    &self.archive_root,
+                    &self.suite,
+                    "Packages"
+                )),
+                stanzas = tracing::field::Empty,
+                with_build_ids = tracing::field::Empty,
+            );
+            let entries = arches
+                .entry(match self.arch_dirs {
+                    true => source.arch.clone(),
+                    false => String::new(),
+                })
+                .or_default();
+            let stanzas = async {
+                let stanzas = self
+                    .index_packages(&source, release.as_ref(), entries)
+                    .await?;
+                let span = tracing::Span::current();
+                span.record("stanzas", stanzas.total);
+                span.record("with_build_ids", stanzas.with_build_ids);
+                Ok::<_, FileError>(stanzas)
+            }
+            .instrument(span)
+            .await?;
+            with_build_ids += stanzas.with_build_ids;
+        }
+        tracing::info!(
+            "{} stanzas with build-ids in the index for {}",
+            with_build_ids,
+            self.suite
+        );
+
+        if !self.arch_dirs {
+            let entries = arches.pop_first().map(|(_, v)| v).unwrap_or_default();
+            self.check_empty(&entries)?;
+            return Ok(Self::tree(entries, self.ns));
+        }
+
+        if arches.values().all(|entries| entries.is_empty()) {
+            self.check_empty(&HashMap::new())?;
+        }
+        Ok(Self::arch_root(arches, self.ns))
+    }
+
+    ///
+    fn check_empty(&self, entries: &HashMap<String, (String, Vec<File>)>) -> FileResult<()> {
+        if self.fail_on_empty && entries.is_empty() {
+            let components = match &self.components {
+                None => "any component".to_owned(),
+                Some(components) => components.join(", "),
+            };
+            tracing::warn!(
+                "no stanzas with build-ids in the index for {} ({}); is that \
+                 the right suite and component of a debug archive?",
+                self.suite,
+                components
+            );
+            return Err(FileError(2, "ENOENT".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Build the directory tree out of the prefix directories in `entries`.
+    #[cfg(test)]
+    fn root(entries: HashMap<String, (String, Vec<File>)>) -> Root {
+        Self::tree(entries, 0)
+    }
+
+    /// Build a tree out of the prefix directories in `entries`, using `ns`
+    /// to keep its qids apart from the trees of other architectures.
+    fn tree(entries: HashMap<String, (String, Vec<File>)>, ns: u64) -> Root {
+        // tokio spawn on a joinset
+
+        Root {
+            // join_set: Arc::new(JoinSet::new()),
+            session: None,
+            build_ids: Arc::new(OnceLock::new()),
+            index: Arc::new(OnceLock::new()),
+            ns,
+            directory: Arc::new(Box::new(Directory::new(
+                "/",
+                ns,
+                entries
+                    .into_iter()
+                    .map(|(_, (name, entries))| File::Directory(Directory::new(&name, ns, entries)))
+                    .collect(),
+            ))),
+        }
+    }
+
+    /// Build a root with a directory per architecture, each holding the
+    /// `.build-id` tree for that architecture.
+    fn arch_root(arches: BTreeMap<String, HashMap<String, (String, Vec<File>)>>, ns: u64) -> Root {
+        let arches = arches
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (name, entries))| {
+                File::Arch(Arch {
+                    name,
+                    root: Self::tree(entries, ns + idx as u64 + 1),
+                })
+            })
+            .collect();
+        Self::container(arches, ns)
+    }
+
+    /// Root that only holds other directories, rather than build-ids.
+    fn container(entries: Vec<File>, ns: u64) -> Root {
+        Root {
+            session: None,
+            build_ids: Arc::new(OnceLock::new()),
+            index: Arc::new(OnceLock::new()),
+            ns,
+            directory: Arc::new(Box::new(Directory::new("/", ns, entries))),
+        }
+    }
+
+    /// The index for this suite, loading it if we don't have one from
+    /// within the TTL, or falling back to a stale one if that fails. Once
+    /// a refresh fails, the stale index is served without trying again
+    /// until another TTL has gone by.
+    async fn current(&self) -> FileResult<Root> {
+        if let Some(root) = self.fresh() {
+            return Ok(root);
+        }
+        let _refreshing = self.refreshing.lock().await;
+        // someone else may have refreshed it while we waited.
+        if let Some(root) = self.fresh() {
+            return Ok(root);
+        }
+
+        let started = Instant::now();
+        let loaded = self.load().await;
+        METRICS.index_refresh.observe(started.elapsed());
+        let err = match loaded {
+            Ok(root) => {
+                let now = Instant::now();
+                *self.cached.lock().unwrap() = Some(Cached {
+                    loaded: now,
+                    attempted: now,
+                    root: root.clone(),
+                });
+                METRICS.index_loaded(&self.suite, now);
+                return Ok(root);
+            }
+            Err(err) => err,
+        };
+
+        if !self.serve_stale {
+            return Err(err);
+        }
+
+        let mut cached = self.cached.lock().unwrap();
+        let cached = match cached.as_mut() {
+            None => return Err(err),
+            Some(v) => v,
+        };
+        cached.attempted = Instant::now();
+        tracing::warn!(
+            staleness = cached.loaded.elapsed().as_secs(),
+            "failed to refresh {}, serving the stale index",
+            self.suite
+        );
+        Ok(cached.root.clone())
+    }
+
+    /// The index we have, if it's within the TTL, or if we're serving it
+    /// stale and last tried to refresh it within the TTL.
+    fn fresh(&self) -> Option<Root> {
+        let cached = self.cached.lock().unwrap();
+        let cached = cached.as_ref()?;
+        let backing_off = self.serve_stale && cached.attempted.elapsed() < self.index_ttl;
+        (cached.loaded.elapsed() < self.index_ttl || backing_off).then(|| cached.root.clone())
+    }
+
+    /// Parse a decompressed `Packages` file, adding every build-id it lists
+    /// to `entries`, keyed by the two-character prefix directory. Returns
+    /// how many stanzas it held.
+    #[cfg(test)]
+    async fn index(
+        &self,
+        packages: Vec<u8>,
+        entries: &mut HashMap<String, (String, Vec<File>)>,
+    ) -> FileResult<Stanzas> {
+        self.index_with(packages, entries, workers()).await
+    }
+
+    /// How our debug files are to be extracted.
+    fn extraction(&self) -> Extraction {
+        Extraction {
+            layout: self.layout.clone(),
+            cache: self.cache.clone(),
+            stat_sizes: self.stat_sizes,
+            strip: self.strip,
+            buffers: self.buffers,
+            fetches: self.fetches.clone(),
+            inflight: self.inflight.clone(),
+            http: self.http.clone(),
+        }
+    }
+
+    /// Parse `packages` in up to `workers` chunks at once, merging them
+    /// back in order so the result is the same as parsing it in one go.
+    #[cfg(test)]
+    async fn index_with(
+        &self,
+        packages: Vec<u8>,
+        entries: &mut HashMap<String, (String, Vec<File>)>,
+        workers: usize,
+    ) -> FileResult<Stanzas> {
+        let chunk = packages.len() / workers.max(1) + 1;
+        self.index_reader("Packages", Cursor::new(packages), entries, workers, chunk)
+            .await
+    }
+
+    /// Parse `packages` as it's read, handing off about `chunk` bytes at a
+    /// time, cut on the blank line between two stanzas, with up to
+    /// `workers` of them being parsed at once. They're merged back in
+    /// order, so the result is the same as parsing it in one go. Fails with
+    /// `EFBIG` once more than our index limit has been read out of `what`.
+    async fn index_reader<R: AsyncRead + Unpin>(
+        &self,
+        what: &str,
+        mut packages: R,
+        entries: &mut HashMap<String, (String, Vec<File>)>,
+        workers: usize,
+        chunk: usize,
+    ) -> FileResult<Stanzas> {
+        let mut join_set = JoinSet::new();
+        let mut chunks = vec![];
+        let mut pending = vec![];
+        let mut block = vec![0u8; 64 * 1024];
+        let mut total = 0u64;
+        let pool_root = self.pool_root.as_ref().unwrap_or(&self.archive_root);
+        for idx in 0.. {
+            // read until there's a whole chunk to hand off, or nothing left.
+            let mut eof = false;
+            let cut = loop {
+                let n = packages
+                    .read(&mut block)
+                    .await
+                    .map_err(|_| read_error(what))?;
+                total += n as u64;
+                check_size(what, total, self.max_index_size)?;
+                pending.extend_from_slice(&block[..n]);
+                if n == 0 {
+                    eof = true;
+                    break pending.len();
+                }
+                if pending.len() < chunk {
+                    continue;
+                }
+                // anything before what we just read was already searched;
+                // keep enough of it for a blank line read in halves.
+                let from = (pending.len() - n).saturating_sub(2);
+                if let Some(v) = stanza_end(&pending[from..]) {
+                    break from + v;
+                }
+            };
+            let rest = pending.split_off(cut);
+            let packages = std::mem::replace(&mut pending, rest);
+
+            while join_set.len() >= workers.max(1) {
+                if let Some(chunk) = join_set.join_next().await {
+                    chunks.push(chunk.map_err(|_| FileError(5, "EIO".to_owned()))?);
+                }
+            }
+            if !packages.is_empty() {
+                let pool_root = pool_root.clone();
+                let extraction = self.extraction();
+                join_set.spawn(async move {
+                    let chunk = index_chunk(&pool_root, &extraction, &packages).await;
+                    (idx, chunk)
+                });
+            }
+            if eof {
+                break;
+            }
+        }
+
+        while let Some(chunk) = join_set.join_next().await {
+            chunks.push(chunk.map_err(|_| FileError(5, "EIO".to_owned()))?);
+        }
+        chunks.sort_by_key(|(idx, _)| *idx);
+
+        let mut stanzas = Stanzas::default();
+        for (_, chunk) in chunks {
+            let (count, chunk) = chunk?;
+            stanzas += count;
+            merge(entries, chunk);
+        }
+        Ok(stanzas)
+    }
+}
+
+/// Where the last whole stanza in `buf` ends, just past the blank line
+/// after it, whether its lines end in `\n` or `\r\n`.
+fn stanza_end(buf: &[u8]) -> Option<usize> {
+    (1..buf.len()).rev().find_map(|idx| match buf[..=idx] {
+        [.., b'\n', b'\n'] | [.., b'\n', b'\r', b'\n'] => Some(idx + 1),
+        _ => None,
+    })
+}
+
+/// How many chunks of a `Packages` file to parse at once.
+fn workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|v| v.get())
+        .unwrap_or(1)
+}
+
+/// Add the prefix directories in `chunk` to those in `entries`.
+fn merge(
+    entries: &mut HashMap<String, (String, Vec<File>)>,
+    chunk: HashMap<String, (String, Vec<File>)>,
+) {
+    for (dir_name, (_, dir_entries)) in chunk {
+        entries
+            .entry(dir_name.clone())
+            .or_insert((dir_name, vec![]))
+            .1
+            .extend(dir_entries);
+    }
+}
+
+/// Settings of the filesystem that every [DebugHeader] in it carries, for
+/// when it's opened.
+#[derive(Debug, Clone)]
+struct Extraction {
+    layout: Arc<dyn Layout>,
+    cache: Option<Arc<DiskCache>>,
+    stat_sizes: bool,
+    strip: bool,
+    buffers: Buffers,
+    fetches: Option<Arc<Semaphore>>,
+    inflight: Inflight,
+    http: Arc<Options>,
+}
+
+impl Default for Extraction {
+    fn default() -> Self {
+        Extraction {
+            layout: Arc::new(Debian),
+            cache: None,
+            stat_sizes: false,
+            strip: false,
+            buffers: Buffers::default(),
+            fetches: None,
+            inflight: Inflight::default(),
+            http: Arc::default(),
+        }
+    }
+}
+
+/// URL of the `.deb` at `filename`, as a `Packages` file lists it, under
+/// `pool_root`. A `Filename` that's already a full `http(s)://` URL is used
+/// as it is; otherwise any leading, trailing or doubled slashes are dropped
+/// so the two join with exactly one.
+fn pool_url(pool_root: &str, filename: &str) -> String {
+    let absolute = filename.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    });
+    if absolute {
+        return filename.to_owned();
+    }
+    let path: Vec<_> = filename.split('/').filter(|v| !v.is_empty()).collect();
+    format!("{}/{}", pool_root.trim_end_matches('/'), path.join("/"))
+}
+
+/// How many stanzas a `Packages` file held, and how many of those listed
+/// build-ids that we serve.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Stanzas {
+    total: usize,
+    with_build_ids: usize,
+}
+
+impl std::ops::AddAssign for Stanzas {
+    fn add_assign(&mut self, other: Self) {
+        self.total += other.total;
+        self.with_build_ids += other.with_build_ids;
+    }
+}
+
+/// Parse the stanzas in `packages`, grouping every build-id they list by
+/// its two-character prefix directory, and counting the stanzas.
+async fn index_chunk(
+    pool_root: &str,
+    extraction: &Extraction,
+    packages: &[u8],
+) -> FileResult<(Stanzas, HashMap<String, (String, Vec<File>)>)> {
+    let mut stanzas = Stanzas::default();
+    let mut entries = HashMap::<String, (String, Vec<File>)>::new();
+    let mut body = Cursor::new(packages);
+    loop {
+        let headers = match deb822::next(&mut body).await {
+            Ok(None) => {
+                break;
+            }
+            Ok(Some(v)) => v,
+            // text that isn't UTF-8 is as malformed as a line without a
+            // colon; anything else is the read itself failing.
+            Err(deb822::Error::Io(err)) if err.kind() != ErrorKind::InvalidData => {
+                return Err(FileError(121, "EREMOTEIO".to_owned()));
+            }
+            Err(err) => {
+                stanzas.total += 1;
+                tracing::warn!("skipping malformed stanza {}: {:?}", stanzas.total, err);
+                deb822::skip(&mut body)
+                    .await
+                    .map_err(|_| FileError(121, "EREMOTEIO".to_owned()))?;
+                continue;
+            }
+        };
+        stanzas.total += 1;
+
+        let stanza = match deb822::PackageStanza::try_from(headers) {
+            Err(_) => {
+                // malformed, or without any build-ids.
+                continue;
+            }
+            Ok(v) => v,
+        };
+        stanzas.with_build_ids += 1;
+
+        let mut text = String::new();
+        if let Some(package) = &stanza.package {
+            text += &format!("Package: {}\n", package);
+        }
+        if let Some(version) = &stanza.version {
+            text += &format!("Version: {}\n", version);
+        }
+        text += &format!("Filename: {}\n", stanza.filename);
+        let text = Arc::new(text);
+        // Ubuntu's debug packages are `.ddeb`s, but otherwise the same.
+        let extension = match stanza.filename.ends_with(".ddeb") {
+            true => "ddeb",
+            false => "deb",
+        };
+
+        for build_id in &stanza.build_ids {
+            let dir_name = build_id[..2].to_owned();
+            let (_, dir_entries) = entries
+                .entry(dir_name.clone())
+                .or_insert((dir_name.clone(), vec![]));
+            let header = DebugHeader {
+                fspath: extraction.layout.debug_path(build_id),
+                build_id: build_id.clone(),
+                name: format!("{}.debug", &build_id[2..]),
+                pool: pool_url(pool_root, &stanza.filename),
+                cache: extraction.cache.clone(),
+                size: Arc::default(),
+                mtime: Arc::default(),
+                sha256: stanza.sha256.clone(),
+                deb_size: stanza.size,
+                version: stanza.version.clone(),
+                stat_sizes: extraction.stat_sizes,
+                strip: extraction.strip,
+                buffers: extraction.buffers,
+                fetches: extraction.fetches.clone(),
+                inflight: extraction.inflight.clone(),
+                http: extraction.http.clone(),
+            };
+            dir_entries.push(File::DebugHeader(header.clone()));
+            dir_entries.push(File::PoolDeb(PoolDeb {
+                name: format!("{}.{}", &build_id[2..], extension),
+                size: Arc::default(),
+                header,
+            }));
+            dir_entries.push(File::Package(Package {
+                name: format!("{}.package", &build_id[2..]),
+                build_id: build_id.clone(),
+                text: text.clone(),
+                version: stanza.version.clone(),
+                package: stanza.package.clone(),
+            }));
+        }
+    }
+    Ok((stanzas, entries))
+}
+
+impl Filesystem for Debug {
+    type File = File;
+
+    async fn attach(&self, aname: &str, _: &str, _: u32) -> FileResult<File> {
+        METRICS.attaches.inc();
+        let span = tracing::info_span!("attach", aname, suite = %self.suite);
+        let root = async {
+            self.check_aname(aname)?;
+            let session = self.session()?;
+
+            let root = self.top().await?;
+            Ok(File::Root(Root { session, ..root }))
+        };
+        METRICS.track(root.instrument(span).await)
+    }
+}
+
+///
+#[derive(Debug, Clone)]
+pub(crate) struct Directory {
+    name: String,
+    /// namespace of the tree this directory is in.
+    ns: u64,
+    /// in the order they're listed.
+    entries: Arc<Vec<File>>,
+    /// position of each entry in `entries`, by name.
+    names: Arc<HashMap<String, usize>>,
+}
+
+impl Directory {
+    ///
+    fn new(name: &str, ns: u64, entries: Vec<File>) -> Self {
+        // the same build-id can be listed more than once, such as by two
+        // components, or by two versions of a package after a binNMU. The
+        // newest version is kept, or the first one listed if that's a tie.
+        let mut names = HashMap::with_capacity(entries.len());
+        let mut kept: Vec<File> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let idx = match names.get(entry.name()) {
+                None => {
+                    names.insert(entry.name().to_owned(), kept.len());
+                    kept.push(entry);
+                    continue;
+                }
+                Some(idx) => *idx,
+            };
+            if let (File::DebugHeader(old), File::DebugHeader(new)) = (&kept[idx], &entry) {
+                if old.pool != new.pool {
+                    tracing::warn!(
+                        build_id = %new.build_id,
+                        "build-id is in more than one package: {} and {}",
+                        redact(&old.pool),
+                        redact(&new.pool)
+                    );
+                }
+            }
+            let newer = match (entry.version(), kept[idx].version()) {
+                (Some(new), Some(old)) => deb822::version_cmp(new, old).is_gt(),
+                _ => false,
+            };
+            if newer {
+                kept[idx] = entry;
+            }
+        }
+        Directory {
+            name: name.to_owned(),
+            ns,
+            entries: Arc::new(kept),
+            names: Arc::new(names),
+        }
+    }
+
+    ///
+    fn lookup(&self, name: &str) -> Option<&File> {
+        self.names.get(name).map(|idx| &self.entries[*idx])
+    }
+    ///
+    async fn open_dir(&self, om: OpenMode) -> FileResult<OpenFile> {
+        match om.direction() {
+            IoDirection::Read => {}
+            _ => return Err(FileError(21, "EISDIR".to_owned())),
+        }
+
+        Ok(OpenFile::Dirents(Dirents::new(self.entries.clone())))
+    }
+}
+
+/// Length of the dirents for `entries`. The size is a fixed width field,
+/// so it doesn't matter what we put there.
+fn dirents_len<'a>(entries: impl Iterator<Item = &'a File>) -> u64 {
+    entries.map(dirent_len).sum()
+}
+
+///
+fn dirent_len(dirent: &File) -> u64 {
+    let mut ent = Cursor::new(vec![]);
+    let _ = dirent.stat_sized(0).dehydrate(&mut ent);
+    ent.into_inner().len() as u64
+}
+
+/// Directory listing, serialized an entry at a time as it's read rather
+/// than all at once when it's opened.
+pub(crate) struct Dirents {
+    entries: Arc<Vec<File>>,
+    /// offset of the end of each entry in the listing, as far as we've
+    /// got so far.
+    ends: Vec<u64>,
+}
+
+impl Dirents {
+    ///
+    fn new(entries: Arc<Vec<File>>) -> Self {
+        Dirents {
+            entries,
+            ends: vec![],
+        }
+    }
+
+    ///
+    fn end(&self) -> u64 {
+        self.ends.last().copied().unwrap_or(0)
+    }
+
+    /// Render the listing starting at `off`, an entry at a time. Only
+    /// whole entries are handed back, as 9p needs; `off` has to be where
+    /// one starts, and `buf` has to fit at least one.
+    fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<usize> {
+        // measure anything between what we've read so far and `off`.
+        while self.end() <= off && self.ends.len() < self.entries.len() {
+            let end = self.end() + dirent_len(&self.entries[self.ends.len()]);
+            self.ends.push(end);
+        }
+
+        let mut idx = self.ends.partition_point(|end| *end <= off);
+        let start = match idx {
+            0 => 0,
+            _ => self.ends[idx - 1],
+        };
+        if idx < self.entries.len() && start != off {
+            return Err(FileError(22, "EINVAL".to_owned()));
+        }
+
+        let mut n = 0;
+        while idx < self.entries.len() {
+            let dirent = &self.entries[idx];
+            let mut ent = Cursor::new(vec![]);
+            if dirent
+                .stat_sized(dirent.size())
+                .dehydrate(&mut ent)
+                .is_err()
+            {
+                return Err(FileError(22, "EINVAL".to_owned()));
+            }
+            let ent = ent.into_inner();
+            if idx == self.ends.len() {
+                self.ends.push(off + n as u64 + ent.len() as u64);
+            }
+
+            if n + ent.len() > buf.len() {
+                break;
+            }
+            buf[n..n + ent.len()].copy_from_slice(&ent);
+            n += ent.len();
+            idx += 1;
+        }
+        if n == 0 && idx < self.entries.len() {
+            return Err(FileError(22, "EINVAL".to_owned()));
+        }
+        Ok(n)
+    }
+}
+
+///
+#[derive(Debug, Clone)]
+pub(crate) struct Root {
+    directory: Arc<Box<Directory>>,
+    // join_set: Arc<JoinSet>,
+    /// held for as long as the client has the root open, if sessions are
+    /// being limited.
+    session: Option<Arc<OwnedSemaphorePermit>>,
+    build_ids: Arc<OnceLock<BuildIds>>,
+    /// offset of the end of each line of `index.tsv`, in the same order as
+    /// `.build-ids`.
+    index: Arc<OnceLock<Vec<u64>>>,
+    /// 0 for the top of the filesystem, otherwise the namespace of an
+    /// architecture's `.build-id` tree.
+    ns: u64,
+}
+
+impl Root {
+    ///
+    async fn open_dir(&self, om: OpenMode) -> FileResult<OpenFile> {
+        match om.direction() {
+            IoDirection::Read => {}
+            _ => return Err(FileError(21, "EISDIR".to_owned())),
+        }
+
+        Ok(OpenFile::Dirents(Dirents::new(Arc::new(self.entries()))))
+    }
+
+    /// Everything in the root, including `.build-ids` and `index.tsv` when
+    /// this is a tree of build-ids rather than a directory of
+    /// architectures or suites.
+    fn entries(&self) -> Vec<File> {
+        let mut entries = self.directory.entries.to_vec();
+        if !self.is_container() {
+            entries.push(File::BuildIds(self.clone()));
+            entries.push(File::Index(self.clone()));
+        }
+        entries
+    }
+
+    ///
+    fn is_container(&self) -> bool {
+        matches!(
+            self.directory.entries.first(),
+            Some(File::Arch(_)) | Some(File::Suite(_))
+        )
+    }
+
+    ///
+    fn build_ids(&self) -> &BuildIds {
+        self.build_ids
+            .get_or_init(|| BuildIds::new(&self.directory))
+    }
+
+    /// Render the `.build-ids` listing starting at `off`, a line at a time.
+    fn read_build_ids(&self, buf: &mut [u8], off: u64) -> usize {
+        let build_ids = self.build_ids();
+        read_lines(&build_ids.ends, buf, off, |line| {
+            format!("{}\n", build_ids.build_id(&self.directory, line))
+        })
+    }
+
+    /// Line `line` of `index.tsv`: the build-id, the package it's in, and
+    /// the package's path in the pool, tab separated. Fields the index
+    /// doesn't have are left empty.
+    fn index_line(&self, line: usize) -> String {
+        let (d, e) = self.build_ids().index[line];
+        let File::Directory(dir) = &self.directory.entries[d] else {
+            return String::new();
+        };
+        let File::DebugHeader(dh) = &dir.entries[e] else {
+            return String::new();
+        };
+        let (package, filename) = match dir.lookup(&format!("{}.package", &dh.build_id[2..])) {
+            Some(File::Package(package)) => (
+                package.package.as_deref().unwrap_or(""),
+                package
+                    .text
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Filename: "))
+                    .unwrap_or(""),
+            ),
+            _ => ("", ""),
+        };
+        format!("{}\t{}\t{}\n", dh.build_id, package, filename)
+    }
+
+    ///
+    fn index_ends(&self) -> &[u64] {
+        self.index.get_or_init(|| {
+            let mut end = 0;
+            (0..self.build_ids().index.len())
+                .map(|line| {
+                    end += self.index_line(line).len() as u64;
+                    end
+                })
+                .collect()
+        })
+    }
+
+    /// Render `index.tsv` starting at `off`, a line at a time.
+    fn read_index(&self, buf: &mut [u8], off: u64) -> usize {
+        read_lines(self.index_ends(), buf, off, |line| self.index_line(line))
+    }
+}
+
+/// Copy what's in `buf` of a file rendered a line at a time by `line`,
+/// starting at `off`, where `ends` is the offset of the end of each line.
+fn read_lines(ends: &[u64], buf: &mut [u8], off: u64, line: impl Fn(usize) -> String) -> usize {
+    let mut idx = ends.partition_point(|end| *end <= off);
+    let mut off = off;
+    let mut n = 0;
+    while n < buf.len() && idx < ends.len() {
+        let start = match idx {
+            0 => 0,
+            _ => ends[idx - 1],
+        };
+        let text = line(idx);
+        let text = &text.as_bytes()[(off - start) as usize..];
+
+        let m = text.len().min(buf.len() - n);
+        buf[n..n + m].copy_from_slice(&text[..m]);
+        n += m;
+        off += m as u64;
+        idx += 1;
+    }
+    n
+}
+
+/// Top-level directory for a single architecture, holding its `.build-id`
+/// tree.
+#[derive(Debug, Clone)]
+pub(crate) struct Arch {
+    name: String,
+    root: Root,
+}
+
+/// Top-level directory for a suite, which loads the suite's index the first
+/// time it's needed.
+#[derive(Clone)]
+pub(crate) struct Suite {
+    name: String,
+    debug: Arc<Debug>,
+}
+
+impl std::fmt::Debug for Suite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Suite").field("name", &self.name).finish()
+    }
+}
+
+impl Suite {
+    /// What's in the suite directory: its `.build-id` tree, or a directory
+    /// per architecture.
+    async fn entries(&self) -> FileResult<Vec<File>> {
+        let root = self.debug.current().await?;
+        Ok(match root.is_container() {
+            true => root.directory.entries.to_vec(),
+            false => vec![File::Root(root)],
+        })
+    }
+}
+
+/// Sorted index of every build-id in the tree, used to stream the
+/// `.build-ids` file without rendering the whole thing up front.
+#[derive(Debug)]
+struct BuildIds {
+    /// position of each `DebugHeader` as (directory, entry), sorted by
+    /// build-id.
+    index: Vec<(usize, usize)>,
+    /// offset of the end of each line in the rendered file.
+    ends: Vec<u64>,
+}
+
+impl BuildIds {
+    ///
+    fn new(directory: &Directory) -> Self {
+        let mut build_ids = BuildIds {
+            index: vec![],
+            ends: vec![],
+        };
+        for (d, dir) in directory.entries.iter().enumerate() {
+            let File::Directory(dir) = dir else {
+                continue;
+            };
+            for (e, entry) in dir.entries.iter().enumerate() {
+                if let File::DebugHeader(_) = entry {
+                    build_ids.index.push((d, e));
+                }
+            }
+        }
+        build_ids
+            .index
+            .sort_by(|a, b| Self::lookup(directory, *a).cmp(Self::lookup(directory, *b)));
+
+        let mut end = 0;
+        for line in 0..build_ids.index.len() {
+            end += build_ids.build_id(directory, line).len() as u64 + 1;
+            build_ids.ends.push(end);
+        }
+        build_ids
+    }
+
+    ///
+    fn build_id<'a>(&self, directory: &'a Directory, line: usize) -> &'a str {
+        Self::lookup(directory, self.index[line])
+    }
+
+    ///
+    fn lookup(directory: &Directory, (d, e): (usize, usize)) -> &str {
+        match &directory.entries[d] {
+            File::Directory(dir) => match &dir.entries[e] {
+                File::DebugHeader(dh) => &dh.build_id,
+                _ => "",
+            },
+            _ => "",
+        }
+    }
+}
+
+/// A cache fill, shared by everyone waiting on it.
+type Fill = Shared<BoxFuture<'static, Result<Arc<CachedFile>, Arc<FileError>>>>;
+
+/// Cache fills in progress, by build-id, so that opens of a debug file
+/// that's already on its way into the cache wait for it rather than
+/// fetching the `.deb` all over again.
+#[derive(Clone, Default)]
+struct Inflight(Arc<Mutex<HashMap<String, Fill>>>);
+
+impl std::fmt::Debug for Inflight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fills = self.0.lock().unwrap().len();
+        f.debug_tuple("Inflight").field(&fills).finish()
+    }
+}
+
+impl Inflight {
+    /// Wait on the fill of `build_id` that's already in progress, or start
+    /// one with `fill` if there isn't one. Everyone waiting gets their own
+    /// handle on the cached file.
+    async fn join<F>(&self, build_id: &str, fill: F) -> FileResult<CachedFile>
+    where
+        F: FnOnce() -> BoxFuture<'static, FileResult<CachedFile>>,
+    {
+        let shared = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(build_id.to_owned())
+            .or_insert_with(|| {
+                fill()
+                    .map(|res| res.map(Arc::new).map_err(Arc::new))
+                    .boxed()
+                    .shared()
+            })
+            .clone();
+        let res = shared.clone().await;
+
+        // done, one way or the other; a fill started since isn't ours to
+        // clear out.
+        let mut fills = self.0.lock().unwrap();
+        if fills.get(build_id).is_some_and(|v| v.ptr_eq(&shared)) {
+            fills.remove(build_id);
+        }
+        drop(fills);
+
+        match res {
+            Ok(file) => file.try_clone().map_err(|err| {
+                tracing::warn!("failed to reopen cached {}: {:?}", build_id, err);
+                FileError(5, "EIO".to_owned())
+            }),
+            Err(err) => Err(FileError(err.0, err.1.clone())),
+        }
+    }
+}
+
+///
+#[derive(Debug, Clone)]
+pub(crate) struct DebugHeader {
+    name: String,
+    build_id: String,
+    pool: String,
+    /// path of the debug file in the package's `data.tar`.
+    fspath: String,
+    cache: Option<Arc<DiskCache>>,
+    /// size of the debug file, once we've seen it.
+    size: Arc<OnceLock<u64>>,
+    /// timestamp of the `data.tar` member it came out of, once we've seen
+    /// it.
+    mtime: Arc<OnceLock<u32>>,
+    /// `SHA256` and `Size` of the `.deb`, from the index.
+    sha256: Option<String>,
+    deb_size: Option<u64>,
+    /// version of the package, if the index says.
+    version: Option<String>,
+    /// whether a stat should go find out the size if we don't know it.
+    stat_sizes: bool,
+    /// whether to serve only the sections a debugger wants; see
+    /// [elf::strip].
+    strip: bool,
+    buffers: Buffers,
+    /// shared by every debug file in the filesystem, to limit how many
+    /// `.deb` files are fetched at once.
+    fetches: Option<Arc<Semaphore>>,
+    inflight: Inflight,
+    /// how we talk to the mirror.
+    http: Arc<Options>,
+}
+
+/// `<build-id>.package`, next to each `.debug`, saying which package and
+/// pool file the debug file comes out of.
+#[derive(Debug, Clone)]
+pub(crate) struct Package {
+    name: String,
+    build_id: String,
+    /// `Package`, `Version` and `Filename`, as deb822 fields.
+    text: Arc<String>,
+    version: Option<String>,
+    /// name of the package, for [Debug::prefetch].
+    package: Option<String>,
+}
+
+///
+#[derive(Debug, Clone)]
+pub(crate) enum File {
+    ///
+    Root(Root),
+
+    ///
+    Directory(Directory),
+
+    ///
+    DebugHeader(DebugHeader),
+
+    /// `.build-ids`, listing every build-id in the tree.
+    BuildIds(Root),
+
+    /// `index.tsv`, saying where every build-id in the tree comes from.
+    Index(Root),
+
+    ///
+    Package(Package),
+
+    ///
+    PoolDeb(PoolDeb),
+
+    ///
+    Arch(Arch),
+
+    ///
+    Suite(Suite),
+}
+
+pub(crate) enum OpenFile {
+    ///
+    Dirents(Dirents),
+
+    ///
+    DebEntry(DebEntry),
+
+    /// A debug file out of the on-disk cache.
+    Disk(std::fs::File),
+
+    /// A debug file out of the on-disk cache, kept compressed.
+    Compressed(Compressed),
+
+    ///
+    BuildIds(Root),
+
+    ///
+    Index(Root),
+
+    ///
+    Package(Arc<String>),
+
+    ///
+    PoolDeb(PoolReader),
+}
+
+/// `<build-id>.deb`, or `.ddeb` if that's what it is, next to each
+/// `.debug`: the pool file it comes out of, as-is, for when the whole
+/// package is wanted.
+#[derive(Debug, Clone)]
+pub(crate) struct PoolDeb {
+    name: String,
+    /// size of the `.deb`, once the mirror has told us.
+    size: Arc<OnceLock<u64>>,
+    header: DebugHeader,
+}
+
+impl PoolDeb {
+    ///
+    async fn open_file(&self, om: OpenMode) -> FileResult<OpenFile> {
+        match om.direction() {
+            IoDirection::Read => {}
+            _ => return Err(FileError(1, "EPERM".to_owned())),
+        }
+        self.open_read().await
+    }
+
+    /// Open the pool file; each read is its own fetch from the mirror.
+    async fn open_read(&self) -> FileResult<OpenFile> {
+        METRICS.deb_fetches.inc();
+        Ok(OpenFile::PoolDeb(PoolReader {
+            file: self.connect().await?,
+            deb: self.clone(),
+        }))
+    }
+
+    /// Ask the mirror for the pool file, noting its size, and making sure
+    /// it's the size the index says it is before it's read.
+    async fn connect(&self) -> FileResult<Arc<dyn source::ReadAt>> {
+        tracing::debug!("opening deb: {}", redact(&self.header.pool));
+        let file = source::open(&self.header.pool, &self.header.http)
+            .await
+            .map_err(|err| self.header.deb_error(err))?;
+        let len = file.content_length();
+        let _ = self.size.set(len);
+        if let Some(size) = self.header.deb_size.filter(|size| *size != len) {
+            return Err(self.header.deb_error(
+                Mismatch {
+                    what: "size",
+                    expected: size.to_string(),
+                    actual: len.to_string(),
+                }
+                .into(),
+            ));
+        }
+        Ok(file)
+    }
+
+    /// Size of the pool file, asking the mirror if we haven't yet.
+    async fn probe_size(&self) -> FileResult<u64> {
+        if let Some(size) = self.size.get() {
+            return Ok(*size);
+        }
+        Ok(self.connect().await?.content_length())
+    }
+}
+
+/// An open [PoolDeb], read a range at a time straight off the mirror.
+pub(crate) struct PoolReader {
+    deb: PoolDeb,
+    file: Arc<dyn source::ReadAt>,
+}
+
+impl PoolReader {
+    /// Fetch the range for `buf`, taking a turn fetching from the mirror
+    /// for just as long as that takes.
+    async fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<usize> {
+        let _permit = self.deb.header.fetch_permit().await?;
+        let mut reader = match self
+            .file
+            .reader_at_to(off, buf.len() as u64)
+            .await
+            .map_err(|err| self.deb.header.deb_error(err))?
+        {
+            None => return Ok(0),
+            Some(v) => v,
+        };
+        let mut n = 0;
+        while n < buf.len() {
+            match reader.read(&mut buf[n..]).await {
+                Ok(0) => break,
+                Ok(v) => n += v,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(self.deb.header.deb_error(err.into())),
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// How much of what's already been read out of a [DebEntry] to hold on
+/// to, so a client can re-read or step back a little.
+const DEB_ENTRY_WINDOW: usize = 1024 * 1024;
+
+struct DebEntry {
+    /// how far into the stream we are; `window` ends here.
+    offset: u64,
+    file: Pin<Box<dyn AsyncRead + Send>>,
+    /// the last bytes read out of `file`.
+    window: Vec<u8>,
+    window_size: usize,
+    /// our turn fetching from the mirror, while `file` is still coming
+    /// off of it; given back at EOF, or when we're dropped.
+    permit: Option<OwnedSemaphorePermit>,
+    /// the debug file `file` is streamed out of, to make sense of the
+    /// mirror failing partway.
+    header: Option<DebugHeader>,
+    /// how `file` failed, if it has.
+    failed: Option<FileError>,
+}
+
+impl DebEntry {
+    ///
+    fn new(file: Pin<Box<dyn AsyncRead + Send>>) -> Self {
+        Self::with_window(file, DEB_ENTRY_WINDOW)
+    }
+
+    ///
+    fn with_window(file: Pin<Box<dyn AsyncRead + Send>>, window_size: usize) -> Self {
+        DebEntry {
+            offset: 0,
+            file,
+            window: vec![],
+            window_size,
+            permit: None,
+            header: None,
+            failed: None,
+        }
+    }
+
+    /// Stream `header` straight off the mirror, holding on to `permit`
+    /// until it runs out.
+    fn with_fetch(mut self, header: &DebugHeader, permit: Option<OwnedSemaphorePermit>) -> Self {
+        self.header = Some(header.clone());
+        self.permit = permit;
+        self
+    }
+
+    /// Read out of the underlying stream, serving anything we've read
+    /// recently out of the window, and reading (and dropping) forward to
+    /// `off` if need be. The reader may hand back fewer bytes than asked
+    /// for, so keep going until `buf` is full or we hit EOF; that way a
+    /// short read only ever means EOF.
+    async fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<u64> {
+        if off < self.offset - self.window.len() as u64 {
+            return Err(FileError(29, "ESPIPE".to_owned()));
+        }
+
+        let want = off + buf.len() as u64;
+        while self.offset < want {
+            if let Some(FileError(errno, name)) = &self.failed {
+                // anything we've already read is gone from the stream, so
+                // hand that back first and let the next read fail.
+                if self.offset > off {
+                    break;
+                }
+                return Err(FileError(*errno, name.clone()));
+            }
+            let mut chunk = vec![0u8; (want - self.offset).min(64 * 1024) as usize];
+            match self.file.read(&mut chunk).await {
+                Ok(0) => {
+                    self.permit = None;
+                    break;
+                }
+                Ok(n) => {
+                    self.window.extend_from_slice(&chunk[..n]);
+                    self.offset += n as u64;
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                // the stream may well look like it ends cleanly after this,
+                // so every read from here on fails the same way.
+                Err(err) => {
+                    self.permit = None;
+                    self.failed = Some(match &self.header {
+                        Some(header) => header.deb_error(err.into()),
+                        None => FileError(5, "EIO".to_owned()),
+                    });
+                    continue;
+                }
+            }
+            // only ever hold on to the window, and what this read needs.
+            let keep = self
+                .window_size
+                .max(self.offset.saturating_sub(off) as usize);
+            if self.window.len() > keep {
+                self.window.drain(..self.window.len() - keep);
+            }
+        }
+
+        let start = self.offset - self.window.len() as u64;
+        let from = ((off - start) as usize).min(self.window.len());
+        let n = (self.window.len() - from).min(buf.len());
+        buf[..n].copy_from_slice(&self.window[from..from + n]);
+
+        if self.window.len() > self.window_size {
+            self.window.drain(..self.window.len() - self.window_size);
+        }
+        Ok(n as u64)
+    }
+}
+
+/// A debug file out of the on-disk cache that's kept compressed, read
+/// through a [DebEntry] as it's decompressed. Reading back further than
+/// the [DebEntry] holds on to starts decompressing again from the top.
+pub(crate) struct Compressed {
+    file: CachedFile,
+    entry: DebEntry,
+}
+
+impl Compressed {
+    ///
+    fn new(file: CachedFile) -> std::io::Result<Self> {
+        let entry = DebEntry::new(file.reader()?);
+        Ok(Compressed { file, entry })
+    }
+
+    ///
+    async fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<u64> {
+        match self.entry.read_at(buf, off).await {
+            Err(FileError(29, _)) => {
+                self.entry = DebEntry::new(
+                    self.file
+                        .reader()
+                        .map_err(|_| FileError(5, "EIO".to_owned()))?,
+                );
+                self.entry.read_at(buf, off).await
+            }
+            v => v,
+        }
+    }
+}
+
+impl DebugHeader {
+    /// Map an error reading the pool `.deb` into something to hand back
+    /// over 9p, calling out packages that are only partially on the mirror.
+    fn deb_error(&self, err: anyhow::Error) -> FileError {
+        if let Some(truncated) = cause::<Truncated>(&err) {
+            tracing::warn!(
+                "incomplete package on mirror for {}: {} ({})",
+                self.build_id,
+                redact(&self.pool),
+                truncated
+            );
+            return FileError(61, "ENODATA".to_owned());
+        }
+        // the member is all there, but what's in it stops short.
+        let cut_short = err.chain().any(|err| {
+            err.downcast_ref::<std::io::Error>()
+                .is_some_and(|err| err.kind() == ErrorKind::UnexpectedEof)
+        });
+        if cause::<TruncatedStream>(&err).is_some() || cut_short {
+            tracing::warn!(
+                "incomplete package on mirror for {}: {} ({})",
+                self.build_id,
+                redact(&self.pool),
+                err
+            );
+            return FileError(61, "ENODATA".to_owned());
+        }
+        if let Some(mismatch) = cause::<Mismatch>(&err) {
+            tracing::warn!(
+                "corrupt package on mirror for {}: {} ({})",
+                self.build_id,
+                redact(&self.pool),
+                mismatch
+            );
+            return FileError(5, "EIO".to_owned());
+        }
+        if let Some(not_a_deb) = cause::<NotADeb>(&err) {
+            tracing::warn!(
+                "bad package on mirror for {}: {} ({})",
+                self.build_id,
+                redact(&self.pool),
+                not_a_deb
+            );
+            return FileError(5, "EIO".to_owned());
+        }
+        if let Some(unsupported) = cause::<Unsupported>(&err) {
+            tracing::warn!(
+                "can't decompress package on mirror for {}: {} ({})",
+                self.build_id,
+                redact(&self.pool),
+                unsupported
+            );
+            return FileError(95, "EOPNOTSUPP".to_owned());
+        }
+        if let Some(timeout) = cause::<Timeout>(&err) {
+            tracing::warn!(
+                "mirror stalled fetching {}: {}",
+                redact(&self.pool),
+                timeout
+            );
+            return FileError(121, "EREMOTEIO".to_owned());
+        }
+        FileError(5, "EIO".to_owned())
+    }
+
+    async fn open_file(&self, om: OpenMode) -> FileResult<OpenFile> {
+        match om.direction() {
+            IoDirection::Read => {}
+            _ => return Err(FileError(1, "EPERM".to_owned())),
+        }
+        self.open_read().await
+    }
+
+    /// Open the debug file to read it, for 9p or otherwise.
+    pub(crate) async fn open_read(&self) -> FileResult<OpenFile> {
+        let span = tracing::info_span!(
+            "open_file",
+            build_id = %self.build_id,
+            pool = %redact(&self.pool),
+            path = tracing::field::Empty,
+        );
+        METRICS.track(self.open_cached().instrument(span).await)
+    }
+
+    /// Size of the debug file, if we've seen it yet.
+    pub(crate) fn known_size(&self) -> Option<u64> {
+        self.size.get().copied()
+    }
+
+    /// Serve the debug file out of the cache if we have it, otherwise
+    /// extract it, filling the cache on the way through.
+    async fn open_cached(&self) -> FileResult<OpenFile> {
+        let cache = match &self.cache {
+            // the entry keeps our turn with the mirror until it's been read
+            // to the end; stripping has to read it all in first anyway.
+            None => {
+                let permit = self.fetch_permit().await?;
+                let entry = self.extract().await?;
+                if !self.strip {
+                    return Ok(OpenFile::DebEntry(entry.with_fetch(self, permit)));
+                }
+                let body = self.strip_entry(entry).await?;
+                drop(permit);
+                return Ok(OpenFile::DebEntry(DebEntry::new(Box::pin(Cursor::new(
+                    body,
+                )))));
+            }
+            Some(v) => v,
+        };
+        if let Some(file) = cache.get(&self.cache_key()).await {
+            tracing::debug!("cache hit for {}", self.build_id);
+            METRICS.cache_hits.inc();
+            if let Ok(size) = file.size() {
+                let _ = self.size.set(size);
+            }
+            return self.open_disk(file);
+        }
+
+        let header = self.clone();
+        let cache = cache.clone();
+        let file = self
+            .inflight
+            .join(&self.cache_key(), move || {
+                async move { header.fill_cache(&cache).await }.boxed()
+            })
+            .await?;
+        self.open_disk(file)
+    }
+
+    /// Serve a debug file out of the cache, as it's kept there.
+    fn open_disk(&self, file: CachedFile) -> FileResult<OpenFile> {
+        match file {
+            CachedFile::Raw(file) => Ok(OpenFile::Disk(file)),
+            file => Ok(OpenFile::Compressed(Compressed::new(file).map_err(
+                |err| {
+                    tracing::warn!("failed to open cached {}: {:?}", self.build_id, err);
+                    FileError(5, "EIO".to_owned())
+                },
+            )?)),
+        }
+    }
+
+    /// Extract the debug file into `cache`, for [Self::open_cached].
+    async fn fill_cache(&self, cache: &DiskCache) -> FileResult<CachedFile> {
+        METRICS.cache_misses.inc();
+        let _permit = self.fetch_permit().await?;
+        let mut entry = self.extract().await?;
+        let cached = match (self.strip, cache.compressed()) {
+            (true, _) => {
+                let elf = self.strip_entry(entry).await?;
+                cache.put(&self.cache_key(), &mut &elf[..]).await
+            }
+            // there's no looking through it once it's compressed, so it's
+            // checked on the way in.
+            (false, true) => {
+                let body = self.read_entry(entry).await?;
+                self.check_build_id(elf::build_id(&body))?;
+                cache.put(&self.cache_key(), &mut &body[..]).await
+            }
+            (false, false) => cache.put(&self.cache_key(), &mut entry.file).await,
+        };
+        let file = cached.map_err(|err| {
+            tracing::warn!("failed to cache {}: {:?}", self.build_id, err);
+            self.deb_error(err.into())
+        })?;
+
+        // anything else was checked on the way through.
+        if let (false, CachedFile::Raw(raw)) = (self.strip, &file) {
+            if let Err(err) = self.check_build_id(elf::build_id_at(raw).as_deref()) {
+                if let Err(err) = cache.remove(&self.cache_key()).await {
+                    tracing::warn!("failed to uncache {}: {:?}", self.build_id, err);
+                }
+                return Err(err);
+            }
+        }
+        Ok(file)
+    }
+
+    /// Make sure the debug file we found is the one we were asked for,
+    /// since a mislabelled or repacked package would otherwise hand out
+    /// the wrong symbols without anyone noticing. Debug files we can't
+    /// find a build-id in are let through.
+    fn check_build_id(&self, found: Option<&[u8]>) -> FileResult<()> {
+        let found = match found {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let found: String = found.iter().map(|b| format!("{b:02x}")).collect();
+        if found.eq_ignore_ascii_case(&self.build_id) {
+            return Ok(());
+        }
+        tracing::warn!(
+            "wrong debug file in {} for {}: it has build-id {}",
+            redact(&self.pool),
+            self.build_id,
+            found
+        );
+        Err(FileError(5, "EIO".to_owned()))
+    }
+
+    /// Size of the debug file, going to the `.deb` for it if we don't know
+    /// it yet. Extracting stops at the tar header, since the size is set as
+    /// soon as the entry is found and the body is never read; through the
+    /// cache, the whole file is cached so that opening it next is cheap.
+    async fn probe_size(&self) -> FileResult<u64> {
+        if let Some(size) = self.size.get() {
+            return Ok(*size);
+        }
+        match (&self.cache, self.strip) {
+            (None, false) => {
+                let _permit = self.fetch_permit().await?;
+                drop(self.extract().await?)
+            }
+            // the size is only known once it's been stripped.
+            _ => drop(self.open_cached().await?),
+        }
+        Ok(self.size.get().copied().unwrap_or(0))
+    }
+
+    /// Name of the debug file in the cache; stripped debug files are kept
+    /// apart, so that changing our minds doesn't serve the wrong one.
+    fn cache_key(&self) -> String {
+        match self.strip {
+            true => format!("{}.stripped", self.build_id),
+            false => self.build_id.clone(),
+        }
+    }
+
+    /// Read all of `entry` off the mirror.
+    async fn read_entry(&self, mut entry: DebEntry) -> FileResult<Vec<u8>> {
+        let mut body = vec![];
+        entry.file.read_to_end(&mut body).await.map_err(|err| {
+            tracing::warn!("failed to read {}: {:?}", self.build_id, err);
+            self.deb_error(err.into())
+        })?;
+        Ok(body)
+    }
+
+    /// Read all of `entry` and strip it with [elf::strip], or hand it back
+    /// whole if it isn't an ELF we can make sense of.
+    async fn strip_entry(&self, entry: DebEntry) -> FileResult<Vec<u8>> {
+        let body = self.read_entry(entry).await?;
+        self.check_build_id(elf::build_id(&body))?;
+        let body = match elf::strip(&body) {
+            Some(stripped) => {
+                tracing::debug!(
+                    "stripped {} from {} to {}",
+                    self.build_id,
+                    body.len(),
+                    stripped.len()
+                );
+                stripped
+            }
+            None => {
+                tracing::warn!("couldn't strip {}, serving all of it", self.build_id);
+                body
+            }
+        };
+        let _ = self.size.set(body.len() as u64);
+        Ok(body)
+    }
+
+    /// Wait for our turn to fetch from the mirror, if fetches are limited.
+    /// Held only while the mirror is being read from: until the debug file
+    /// is cached, stripped, or read to the end, or a range of a pool file
+    /// is read.
+    async fn fetch_permit(&self) -> FileResult<Option<OwnedSemaphorePermit>> {
+        let fetches = match &self.fetches {
+            None => return Ok(None),
+            Some(v) => v.clone(),
+        };
+        fetches
+            .acquire_owned()
+            .await
+            .map(Some)
+            .map_err(|_| FileError(5, "EIO".to_owned()))
+    }
+
+    /// Fetch the pool `.deb` and pull our debug file out of it.
+    async fn extract(&self) -> FileResult<DebEntry> {
+        tracing::debug!("opening deb: {}", redact(&self.pool));
+        METRICS.deb_fetches.inc();
+        let started = Instant::now();
+        let mut deb = Deb::open(&self.pool, &self.http)
+            .await
+            .map_err(|err| self.deb_error(err))?;
+        if let Some(sha256) = &self.sha256 {
+            deb = deb
+                .with_checksum(self.deb_size, sha256)
+                .map_err(|err| self.deb_error(err))?;
+        }
+        let mut deb = deb
+            .check_format()
+            .await
+            .map_err(|err| self.deb_error(err))?;
+
+        loop {
+            let entry = match deb.next().await.map_err(|err| self.deb_error(err))? {
+                None => return Err(FileError(5, "EIO".to_owned())),
+                Some(v) => v,
+            };
+            tracing::debug!("loaded entry {:?}", entry.header());
+
+            let identifier = entry.header().identifier.clone();
+            let timestamp = entry.header().timestamp;
+            let data = match data_tar(&identifier, entry.into_body(), self.buffers)
+                .await
+                .map_err(|err| self.deb_error(err))?
+            {
+                None => continue,
+                Some(v) => v,
+            };
+            let mut ar = Archive::new(data);
+            tracing::debug!("stream decompressing");
+
+            let mut entries = ar.entries().map_err(|err| self.deb_error(err.into()))?;
+            while let Some(file) = entries.next().await {
+                let file = file.map_err(|err| self.deb_error(err.into()))?;
+                tracing::debug!("found file {:?}", file.path());
+
+                let path = file.path().map_err(|_| FileError(5, "EIO".to_owned()))?;
+                if same_tar_path(&path, &self.fspath) {
+                    tracing::Span::current().record("path", tracing::field::debug(file.path()));
+                    // stripping changes the size; it's set once that's done.
+                    if let (false, Ok(size)) = (self.strip, file.header().size()) {
+                        let _ = self.size.set(size);
+                    }
+                    let _ = self.mtime.set(timestamp.try_into().unwrap_or(u32::MAX));
+                    // hand the entry back as-is; reads pull it off the wire
+                    // as the client asks for it, and the checksum is only
+                    // done once it's all been read.
+                    let file: Pin<Box<dyn AsyncRead + Send>> = match deb.checksum() {
+                        None => Box::pin(file),
+                        Some(checksum) => Box::pin(checksum.verify(file)),
+                    };
+                    METRICS.extract.observe(started.elapsed());
+                    return Ok(DebEntry::new(file));
+                }
+            }
+
+            // the package came down fine, it just doesn't have the file
+            // the index says it does.
+            tracing::warn!("{} is not in {}", self.fspath, redact(&self.pool));
+            return Err(FileError(2, "ENOENT".to_owned()));
+        }
+    }
+}
+
+/// The first `T` in the chain of errors behind `err`, looking inside any
+/// [std::io::Error] on the way, since that's how they come out of readers.
+fn cause<T: std::error::Error + 'static>(err: &anyhow::Error) -> Option<&T> {
+    err.chain().find_map(|err| {
+        err.downcast_ref::<T>().or_else(|| {
+            err.downcast_ref::<std::io::Error>()?
+                .get_ref()?
+                .downcast_ref::<T>()
+        })
+    })
+}
+
+/// Whether `path`, of a member of a `data.tar`, is the file at `want`,
+/// going by their components, so that a leading `./` or `/`, or doubled or
+/// trailing slashes, on either side don't matter.
+fn same_tar_path(path: &Path, want: &str) -> bool {
+    fn components(path: &Path) -> impl Iterator<Item = Component<'_>> {
+        path.components()
+            .filter(|v| !matches!(v, Component::CurDir | Component::RootDir))
+    }
+    components(path).eq(components(Path::new(want)))
+}
+
+/// The first 16 characters of `name` read as hex, or a hash of the whole
+/// thing if that doesn't work, so that an odd name from the index can't
+/// take the server down.
+fn hex_or_hash(name: &str) -> u64 {
+    let prefix = name.get(..16).unwrap_or(name);
+    u64::from_str_radix(prefix, 16).unwrap_or_else(|_| {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        hasher.finish()
+    })
+}
+
+impl File {
+    /// Version of the package a debug file or `.package` comes from.
+    fn version(&self) -> Option<&str> {
+        match self {
+            Self::DebugHeader(dh) => dh.version.as_deref(),
+            Self::Package(package) => package.version.as_deref(),
+            Self::PoolDeb(deb) => deb.header.version.as_deref(),
+            _ => None,
+        }
+    }
+
+    ///
+    fn stat_sized(&self, size: u64) -> Stat {
+        let sb = Stat::builder(self.name(), self.qid())
+            .with_nuid(0)
+            .with_ngid(0)
+            .with_nmuid(0)
+            .with_size(size)
+            .with_mtime(self.mtime());
+
+        let sb = match self {
+            Self::Root(_) => sb.with_mode(0o555),
+            Self::Directory(_) => sb.with_mode(0o555),
+            Self::Arch(_) => sb.with_mode(0o555),
+            Self::Suite(_) => sb.with_mode(0o555),
+            Self::DebugHeader(_) => sb.with_mode(0o444),
+            Self::BuildIds(_) => sb.with_mode(0o444),
+            Self::Index(_) => sb.with_mode(0o444),
+            Self::Package(_) => sb.with_mode(0o444),
+            Self::PoolDeb(_) => sb.with_mode(0o444),
+        };
+
+        sb.build()
+    }
+
+    /// Size to report in the stat. Directories are as long as their
+    /// serialized dirents, and debug files are 0 until the first time
+    /// they're extracted, since the index doesn't tell us.
+    fn size(&self) -> u64 {
+        match self {
+            Self::Root(root) => dirents_len(root.entries().iter()),
+            Self::Directory(dir) => dirents_len(dir.entries.iter()),
+            Self::Arch(arch) => dirents_len([File::Root(arch.root.clone())].iter()),
+            // we don't want a stat to load the index.
+            Self::Suite(_) => 0,
+            Self::DebugHeader(dh) => dh.size.get().copied().unwrap_or(0),
+            Self::BuildIds(root) => root.build_ids().ends.last().copied().unwrap_or(0),
+            Self::Index(root) => root.index_ends().last().copied().unwrap_or(0),
+            Self::Package(package) => package.text.len() as u64,
+            // the index's `Size` will do until we've asked the mirror.
+            Self::PoolDeb(deb) => deb.size.get().copied().or(deb.header.deb_size).unwrap_or(0),
+        }
+    }
+
+    /// Modification time to report in the stat: when the debug file was
+    /// packaged, once it's been extracted, and the newest of those for a
+    /// prefix directory. Everything else is 0.
+    fn mtime(&self) -> u32 {
+        match self {
+            Self::Directory(dir) => dir.entries.iter().map(File::mtime).max().unwrap_or(0),
+            Self::DebugHeader(dh) => dh.mtime.get().copied().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Path for the qid, unique across every tree in the filesystem.
+    fn qid_path(&self) -> u64 {
+        match self {
+            Self::Root(root) if root.ns == 0 => 0x01,
+            Self::Root(root) => ARCH_QID - 8 * root.ns,
+            Self::Directory(dir) => (dir.ns << 8) | (hex_or_hash(&dir.name) & 0xff),
+            Self::Arch(arch) => ARCH_QID - 8 * arch.root.ns - 2,
+            Self::Suite(suite) => ARCH_QID - 8 * suite.debug.ns - 3,
+            Self::DebugHeader(dh) => hex_or_hash(&dh.build_id),
+            Self::BuildIds(root) if root.ns == 0 => BUILD_IDS_QID,
+            Self::BuildIds(root) => ARCH_QID - 8 * root.ns - 1,
+            Self::Index(root) if root.ns == 0 => INDEX_QID,
+            Self::Index(root) => ARCH_QID - 8 * root.ns - 4,
+            // the complement of the `.debug`, which is as unlikely to land
+            // on anything else as the build-id itself.
+            Self::Package(package) => !hex_or_hash(&package.build_id),
+            // and the halves swapped, for the `.deb`.
+            Self::PoolDeb(deb) => hex_or_hash(&deb.header.build_id).rotate_left(32),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Root(root) if root.ns == 0 => "/",
+            Self::Root(_) => ".build-id",
+            Self::Directory(dir) => &dir.name,
+            Self::Arch(arch) => &arch.name,
+            Self::Suite(suite) => &suite.name,
+            Self::DebugHeader(dbg) => &dbg.name,
+            Self::BuildIds(_) => ".build-ids",
+            Self::Index(_) => "index.tsv",
+            Self::Package(package) => &package.name,
+            Self::PoolDeb(deb) => &deb.name,
+        }
+    }
+
+    async fn walk_to(&self, path: &str) -> FileResult<Self> {
+        match self {
+            Self::Root(root) => {
+                if path == ".build-ids" && !root.is_container() {
+                    return Ok(Self::BuildIds(root.clone()));
+                }
+                if path == "index.tsv" && !root.is_container() {
+                    return Ok(Self::Index(root.clone()));
+                }
+                if let Some(entry) = root.directory.lookup(path) {
+                    return Ok(entry.clone());
+                }
+            }
+            Self::Arch(arch) => {
+                if path == ".build-id" {
+                    return Ok(Self::Root(arch.root.clone()));
+                }
+            }
+            Self::Suite(suite) => {
+                for entry in suite.entries().await? {
+                    if entry.name() == path {
+                        return Ok(entry);
+                    }
+                }
+            }
+            Self::Directory(dir) => {
+                if let Some(entry) = dir.lookup(path) {
+                    return Ok(entry.clone());
+                }
+            }
+            Self::DebugHeader(_)
+            | Self::BuildIds(_)
+            | Self::Index(_)
+            | Self::Package(_)
+            | Self::PoolDeb(_) => {
+                return Err(FileError(20, "ENOTDIR".to_owned()));
+            }
+        };
+        Err(FileError(2, "ENOENT".to_owned()))
+    }
+}
+
+impl FileTrait for File {
+    type OpenFile = OpenFile;
+
+    async fn stat(&self) -> FileResult<Stat> {
+        if let Self::DebugHeader(dh) = self {
+            if dh.stat_sizes {
+                // the stat still works without the size; the read will
+                // turn up whatever went wrong.
+                let size = dh.probe_size().await.unwrap_or_else(|err| {
+                    tracing::debug!("couldn't size {}: {:?}", dh.build_id, err);
+                    0
+                });
+                return Ok(self.stat_sized(size));
+            }
+        }
+        if let Self::PoolDeb(deb) = self {
+            // the size is the mirror's, not the index's, which may be wrong.
+            let size = deb.probe_size().await.unwrap_or_else(|err| {
+                tracing::debug!("couldn't size {}: {:?}", redact(&deb.header.pool), err);
+                0
+            });
+            return Ok(self.stat_sized(size));
+        }
+        Ok(self.stat_sized(self.size()))
+    }
+
+    async fn wstat(&mut self, _: &Stat) -> FileResult<()> {
+        Err(FileError(1, "EPERM".to_owned()))
+    }
+
+    async fn walk(&self, path: &[&str]) -> FileResult<(Option<Self>, Vec<Self>)> {
+        if path.is_empty() {
+            return Ok((Some(self.clone()), vec![]));
+        }
+
+        let mut my_path = self.clone();
+        let mut walked_path = vec![];
+        for part in path {
+            my_path = match my_path.walk_to(part).await {
+                Ok(v) => {
+                    walked_path.push(my_path);
+                    v
+                }
+                // a short walk is how 9p says the rest isn't there, but
+                // anything else, like walking through a file, is worth
+                // saying when it's the very first step.
+                Err(FileError(2, _)) => return Ok((None, walked_path)),
+                Err(err) if walked_path.is_empty() => return Err(err),
+                Err(_) => return Ok((None, walked_path)),
+            };
+        }
+
+        Ok((Some(my_path), walked_path))
+    }
+
+    async fn unlink(&mut self) -> FileResult<()> {
+        Err(FileError(1, "EPERM".to_owned()))
+    }
+
+    async fn create(
+        &mut self,
+        _: &str,
+        _: u16,
+        _: arigato::raw::FileType,
+        _: OpenMode,
+        _: &str,
+    ) -> FileResult<Self> {
+        Err(FileError(1, "EPERM".to_owned()))
+    }
+
+    async fn open(&mut self, om: OpenMode) -> FileResult<OpenFile> {
+        match self {
+            Self::Directory(dir) => dir.open_dir(om).await,
+            Self::Root(root) => root.open_dir(om).await,
+            Self::Arch(arch) => match om.direction() {
+                IoDirection::Read => {
+                    Ok(OpenFile::Dirents(Dirents::new(Arc::new(vec![File::Root(
+                        arch.root.clone(),
+                    )]))))
+                }
+                _ => Err(FileError(21, "EISDIR".to_owned())),
+            },
+            Self::Suite(suite) => match om.direction() {
+                IoDirection::Read => Ok(OpenFile::Dirents(Dirents::new(Arc::new(
+                    suite.entries().await?,
+                )))),
+                _ => Err(FileError(21, "EISDIR".to_owned())),
+            },
+            Self::DebugHeader(dh) => dh.open_file(om).await,
+            Self::BuildIds(root) => match om.direction() {
+                IoDirection::Read => Ok(OpenFile::BuildIds(root.clone())),
+                _ => Err(FileError(1, "EPERM".to_owned())),
+            },
+            Self::Index(root) => match om.direction() {
+                IoDirection::Read => Ok(OpenFile::Index(root.clone())),
+                _ => Err(FileError(1, "EPERM".to_owned())),
+            },
+            Self::Package(package) => match om.direction() {
+                IoDirection::Read => Ok(OpenFile::Package(package.text.clone())),
+                _ => Err(FileError(1, "EPERM".to_owned())),
+            },
+            Self::PoolDeb(deb) => deb.open_file(om).await,
+        }
+    }
+
+    fn qid(&self) -> Qid {
+        let kind = match self {
+            Self::DebugHeader(_)
+            | Self::BuildIds(_)
+            | Self::Index(_)
+            | Self::Package(_)
+            | Self::PoolDeb(_) => FileType::File,
+            _ => FileType::Dir,
+        };
+        Qid::new(kind, 0x01, self.qid_path())
+    }
+}
+
+impl OpenFileTrait for OpenFile {
+    fn iounit(&self) -> u32 {
+        0
+    }
+
+    async fn read_at(&mut self, buf: &mut [u8], off: u64) -> FileResult<u32> {
+        match self {
+            Self::DebEntry(file) => Ok(file.read_at(buf, off).await?.try_into().unwrap()),
+            Self::Dirents(dirents) => Ok(dirents.read_at(buf, off)?.try_into().unwrap()),
+            Self::Disk(file) => Ok(file.read_at(buf, off)?.try_into().unwrap()),
+            Self::Compressed(file) => Ok(file.read_at(buf, off).await?.try_into().unwrap()),
+            Self::BuildIds(root) => Ok(root.read_build_ids(buf, off).try_into().unwrap()),
+            Self::Index(root) => Ok(root.read_index(buf, off).try_into().unwrap()),
+            Self::Package(text) => {
+                let text = text.as_bytes();
+                let start = (off as usize).min(text.len());
+                let n = (text.len() - start).min(buf.len());
+                buf[..n].copy_from_slice(&text[start..start + n]);
+                Ok(n.try_into().unwrap())
+            }
+            Self::PoolDeb(file) => Ok(file.read_at(buf, off).await?.try_into().unwrap()),
+        }
+    }
+
+    async fn write_at(&mut self, _buf: &mut [u8], _off: u64) -> FileResult<u32> {
+        Err(FileError(1, "EPERM".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        deb822, dirent_len, elf, fetch, index_chunk, keyring, pool_url, same_tar_path, sources,
+        stanza_end, Buffers, Cached, DebEntry, Debian, Debug, DebugHeader, Decompress, Directory,
+        Dirents, DiskCache, Extraction, File, Inflight, Layout, OpenFile, Options, Source,
+        DEB_ENTRY_WINDOW,
+    };
+    use crate::{
+        layout::Ddebs,
+        testing::{self, Mirror},
+    };
+    use arigato::{
+        raw::Dehydrate,
+        server::{File as FileTrait, FileError, Filesystem, OpenFile as OpenFileTrait},
+    };
+    use std::{
+        collections::HashMap,
+        io::Cursor,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    };
+    use tokio::{
+        io::{AsyncRead, ReadBuf},
+        sync::Semaphore,
+    };
+
+    /// Reader that never returns more than a few bytes at a time.
+    struct Chunked(Cursor<Vec<u8>>);
+
+    impl AsyncRead for Chunked {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let mut chunk = vec![0u8; buf.remaining().min(3)];
+            let n = std::io::Read::read(&mut self.0, &mut chunk)?;
+            buf.put_slice(&chunk[..n]);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    const PACKAGES: &str = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5 49a0ba466e7cea361ccb59d054ba9986a1ab7824
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+";
+
+    const RELEASE: &str = "Origin: Debian
+Suite: unstable-debug
+Codename: sid-debug
+Architectures: all amd64 arm64
+Components: main contrib
+SHA256:
+ 0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef 1234 main/binary-amd64/Packages.xz
+ fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210 5678 main/binary-arm64/Packages.xz
+";
+
+    /// Read all of `file` sequentially, the way a 9p client would.
+    async fn read_all(mut file: OpenFile) -> Vec<u8> {
+        let mut body = vec![];
+        let mut buf = [0u8; 7];
+        loop {
+            let n = file.read_at(&mut buf, body.len() as u64).await.unwrap() as usize;
+            if n == 0 {
+                return body;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    /// The debug file for `build_id`, out of the `.deb` at `pool`, with
+    /// everything else left at its default.
+    fn debug_header(pool: &str, build_id: &str) -> DebugHeader {
+        DebugHeader {
+            name: format!("{}.debug", &build_id[2..]),
+            build_id: build_id.to_owned(),
+            pool: pool.to_owned(),
+            fspath: format!(
+                "./usr/lib/debug/.build-id/{}/{}.debug",
+                &build_id[..2],
+                &build_id[2..]
+            ),
+            cache: None,
+            size: Arc::default(),
+            mtime: Arc::default(),
+            sha256: None,
+            deb_size: None,
+            version: None,
+            stat_sizes: false,
+            strip: false,
+            buffers: Buffers::default(),
+            fetches: None,
+            inflight: Inflight::default(),
+            http: Arc::default(),
+        }
+    }
+
+    /// Read all of a directory listing, a handful of entries at a time.
+    async fn read_dirents(mut file: OpenFile) -> Vec<u8> {
+        let mut body = vec![];
+        let mut buf = [0u8; 512];
+        loop {
+            let n = file.read_at(&mut buf, body.len() as u64).await.unwrap() as usize;
+            if n == 0 {
+                return body;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    fn source(component: &str, arch: &str) -> Source {
+        Source {
+            component: component.to_owned(),
+            arch: arch.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sources_from_release() {
+        let release = deb822::next(&mut Cursor::new(RELEASE))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                source("main", "amd64"),
+                source("main", "arm64"),
+                source("contrib", "amd64"),
+                source("contrib", "arm64"),
+            ],
+            sources(Some(&release), None, None)
+        );
+
+        assert_eq!(
+            vec![source("main", "arm64"), source("contrib", "arm64")],
+            sources(Some(&release), None, Some(&["arm64".to_owned()]))
+        );
+    }
+
+    #[tokio::test]
+    async fn packages_by_hash() {
+        let release = deb822::next(&mut Cursor::new(RELEASE))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            Some(
+                "http://deb.debian.org/debian-debug/dists/sid-debug/main/binary-arm64/by-hash/SHA256/fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210"
+                    .to_owned()
+            ),
+            source("main", "arm64").packages_by_hash(
+                &Debian,
+                "http://deb.debian.org/debian-debug",
+                "sid-debug",
+                &release
+            )
+        );
+        assert_eq!(
+            None,
+            source("contrib", "arm64").packages_by_hash(
+                &Debian,
+                "http://deb.debian.org/debian-debug",
+                "sid-debug",
+                &release
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn check_packages() {
+        let release = format!(
+            "Suite: unstable-debug\nSHA256:\n {} 1234 main/binary-amd64/Packages.xz\n",
+            keyring::sha256(b"packages")
+        );
+        let release = deb822::next(&mut Cursor::new(release))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let amd64 = source("main", "amd64");
+        assert!(debug
+            .check_packages(&amd64, None, "Packages.xz", &keyring::sha256(b"anything"))
+            .is_ok());
+
+        let debug = debug.with_keyring(std::path::Path::new("/dev/null"));
+        assert!(debug
+            .check_packages(
+                &amd64,
+                Some(&release),
+                "Packages.xz",
+                &keyring::sha256(b"packages")
+            )
+            .is_ok());
+        assert!(matches!(
+            debug.check_packages(
+                &amd64,
+                Some(&release),
+                "Packages.xz",
+                &keyring::sha256(b"tampered")
+            ),
+            Err(FileError(5, _))
+        ));
+        assert!(matches!(
+            debug.check_packages(
+                &amd64,
+                Some(&release),
+                "Packages.gz",
+                &keyring::sha256(b"packages")
+            ),
+            Err(FileError(5, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn max_index_size() {
+        let packages = PACKAGES.repeat(100);
+        let addr = Mirror::new(&[(
+            "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+            testing::xz(packages.as_bytes()),
+        )])
+        .listen_tcp()
+        .await;
+
+        // the compressed file is well under the limit; it's what it
+        // decompresses to that isn't.
+        for (limit, ok) in [(packages.len() as u64, true), (1024, false), (0, true)] {
+            let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+                .with_max_index_size(limit);
+            match debug.attach("", "", 0).await {
+                Ok(_) => assert!(ok, "{limit}"),
+                Err(FileError(27, _)) => assert!(!ok, "{limit}"),
+                Err(FileError(_, name)) => panic!("{limit}: {name}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_local() {
+        let path = std::env::temp_dir().join(format!("debugfs-{}-Packages", std::process::id()));
+        tokio::fs::write(&path, b"Package: a\n").await.unwrap();
+        assert_eq!(
+            b"Package: a\n".to_vec(),
+            fetch(
+                &format!("file://{}", path.display()),
+                11,
+                &Options::default()
+            )
+            .await
+            .unwrap()
+        );
+        assert!(matches!(
+            fetch(
+                &format!("file://{}", path.display()),
+                10,
+                &Options::default()
+            )
+            .await,
+            Err(FileError(27, _))
+        ));
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(matches!(
+            fetch(
+                &format!("file://{}", path.display()),
+                11,
+                &Options::default()
+            )
+            .await,
+            Err(FileError(2, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetch_remote() {
+        let mirror = Mirror::new(&[("/Packages", b"Package: a\n".to_vec())]);
+        let socket = mirror.listen_unix();
+
+        // over a unix socket, with the same headers as any pool fetch.
+        let uri = testing::unix_uri(&socket, "/Packages").replacen("://", "://user:pw@", 1);
+        assert_eq!(
+            b"Package: a\n".to_vec(),
+            fetch(&uri, 11, &Options::default()).await.unwrap()
+        );
+        let headers = &mirror.requests()[0];
+        assert!(headers["user-agent"].starts_with("debugfs/"));
+        assert_eq!("Basic dXNlcjpwdw==", headers["authorization"]);
+
+        assert!(matches!(
+            fetch(&uri, 10, &Options::default()).await,
+            Err(FileError(27, _))
+        ));
+        assert!(matches!(
+            fetch(
+                &testing::unix_uri(&socket, "/Sources"),
+                11,
+                &Options::default()
+            )
+            .await,
+            Err(FileError(2, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn http_options() {
+        let debug_file = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug_file[..],
+        )])
+        .await;
+        let mirror = Mirror::new(&[
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+            ("/pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb", deb),
+        ]);
+        let addr = mirror.listen_tcp().await;
+
+        // the index and the `.deb` are both fetched the way we're told.
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_http_options(Options::default().with_user_agent("debugfs-test/1"));
+        let header = debug
+            .lookup("1c54e04fcf760c428d0afa79a33ffb8e068d35d5")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            debug_file,
+            read_all(header.open_read().await.unwrap()).await
+        );
+        let requests = mirror.requests();
+        assert!(requests.len() > 1);
+        for headers in requests {
+            assert_eq!("debugfs-test/1", headers["user-agent"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn load_by_hash() {
+        let addr = Mirror::new(&[
+            ("/dists/unstable-debug/Release", RELEASE.as_bytes().to_vec()),
+            (
+                "/dists/unstable-debug/main/binary-amd64/by-hash/SHA256/0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+        ])
+        .listen_tcp()
+        .await;
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_by_hash(true);
+
+        let root = debug.attach("", "", 0).await.unwrap();
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        assert!(matches!(file, Some(File::DebugHeader(_))));
+    }
+
+    #[tokio::test]
+    async fn components_dedupe() {
+        let release = "Components: main contrib\nArchitectures: all amd64\n";
+        let addr = Mirror::new(&[
+            ("/dists/unstable-debug/Release", release.as_bytes().to_vec()),
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+            (
+                "/dists/unstable-debug/contrib/binary-amd64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+        ])
+        .listen_tcp()
+        .await;
+        let debug = Debug::discover(&format!("http://{addr}"), "unstable-debug");
+
+        let root = debug.attach("", "", 0).await.unwrap();
+        let (dir, _) = root.walk(&["1c"]).await.unwrap();
+        let Some(File::Directory(dir)) = dir else {
+            panic!("no 1c directory");
+        };
+        // one .debug, .deb and .package, rather than one of each per
+        // component.
+        assert_eq!(3, dir.entries.len());
+
+        let File::Root(root) = root else {
+            panic!("attach didn't return the root");
+        };
+        assert_eq!(2, root.build_ids().index.len());
+    }
+
+    #[tokio::test]
+    async fn packages_fallback() {
+        for (name, body) in [
+            ("Packages.gz", testing::gzip(PACKAGES.as_bytes()).await),
+            ("Packages", PACKAGES.as_bytes().to_vec()),
+        ] {
+            let addr = Mirror::new(&[(
+                &format!("/dists/unstable-debug/main/binary-amd64/{name}")[..],
+                body,
+            )])
+            .listen_tcp()
+            .await;
+            let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64");
+
+            let root = debug.attach("", "", 0).await.unwrap();
+            let (file, _) = root
+                .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+                .await
+                .unwrap();
+            assert!(matches!(file, Some(File::DebugHeader(_))), "{name}");
+        }
+    }
+
+    /// Everything from attach to reading a debug file, against a mirror
+    /// serving a `Packages.xz` and the `.deb` it points at.
+    #[tokio::test]
+    async fn end_to_end() {
+        let debug_file = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug_file[..],
+        )])
+        .await;
+        let packages = format!(
+            "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+Size: {}
+SHA256: {}
+",
+            deb.len(),
+            keyring::sha256(&deb)
+        );
+        let addr = Mirror::new(&[
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(packages.as_bytes()),
+            ),
+            ("/pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb", deb),
+        ])
+        .listen_tcp()
+        .await;
+
+        let cache = std::env::temp_dir().join(format!("debugfs-{}-e2e", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cache);
+        let cache = Arc::new(DiskCache::new(&cache, 1024 * 1024).unwrap());
+
+        for cache in [None, Some(cache)] {
+            let mut debug =
+                Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64");
+            if let Some(cache) = &cache {
+                debug = debug.with_cache(cache.clone());
+            }
+
+            let root = debug.attach("", "", 0).await.unwrap();
+            let (file, _) = root
+                .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+                .await
+                .unwrap();
+            let Some(File::DebugHeader(dh)) = file else {
+                panic!("no debug file");
+            };
+            let file = dh.open_cached().await.unwrap();
+            assert_eq!(debug_file, read_all(file).await);
+            assert_eq!(debug_file.len() as u64, File::DebugHeader(dh).size());
+        }
+    }
+
+    /// Everything in one directory per suite, and debug files named after
+    /// the whole build-id.
+    #[derive(Debug)]
+    struct Flat;
+
+    impl Layout for Flat {
+        fn suite_dir(&self, suite: &str) -> String {
+            format!("debug/{suite}")
+        }
+
+        fn index_dir(&self, component: &str, arch: &str) -> String {
+            format!("{component}-{arch}")
+        }
+
+        fn debug_path(&self, build_id: &str) -> String {
+            format!("./debug/{build_id}")
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_layout() {
+        let debug_file = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./debug/1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            &debug_file[..],
+        )])
+        .await;
+        let packages = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/zzuf-dbgsym.deb
+";
+        let addr = Mirror::new(&[
+            (
+                "/debug/unstable-debug/main-amd64/Packages.xz",
+                testing::xz(packages.as_bytes()),
+            ),
+            ("/pool/zzuf-dbgsym.deb", deb),
+        ])
+        .listen_tcp()
+        .await;
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_layout(Arc::new(Flat));
+        assert_eq!(
+            vec![format!(
+                "http://{addr}/debug/unstable-debug/main-amd64/Packages.xz"
+            )],
+            debug.packages_urls()
+        );
+
+        // the tree we serve is the same whatever the archive looks like.
+        let root = debug.attach("", "", 0).await.unwrap();
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        let Some(File::DebugHeader(dh)) = file else {
+            panic!("no debug file");
+        };
+        assert_eq!(debug_file, read_all(dh.open_cached().await.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn deb_entry_short_reads() {
+        let body: Vec<u8> = (0..25).collect();
+        let mut entry = DebEntry::with_window(Box::pin(Chunked(Cursor::new(body.clone()))), 8);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(10, entry.read_at(&mut buf, 0).await.unwrap());
+        assert_eq!(&body[..10], &buf);
+
+        // too far back for the window.
+        assert!(matches!(
+            entry.read_at(&mut buf, 0).await,
+            Err(FileError(29, _))
+        ));
+
+        assert_eq!(10, entry.read_at(&mut buf, 10).await.unwrap());
+        assert_eq!(&body[10..20], &buf);
+
+        assert_eq!(5, entry.read_at(&mut buf, 20).await.unwrap());
+        assert_eq!(&body[20..], &buf[..5]);
+
+        assert_eq!(0, entry.read_at(&mut buf, 25).await.unwrap());
+        assert_eq!(25, entry.offset);
+    }
+
+    #[tokio::test]
+    async fn deb_entry_window() {
+        let body: Vec<u8> = (0..100).collect();
+        let mut entry = DebEntry::with_window(Box::pin(Chunked(Cursor::new(body.clone()))), 16);
+
+        let mut buf = [0u8; 10];
+        assert_eq!(10, entry.read_at(&mut buf, 0).await.unwrap());
+        // the same read again, and one a little behind.
+        assert_eq!(10, entry.read_at(&mut buf, 0).await.unwrap());
+        assert_eq!(&body[..10], &buf);
+        assert_eq!(10, entry.read_at(&mut buf, 5).await.unwrap());
+        assert_eq!(&body[5..15], &buf);
+
+        // skipping ahead reads through what's in between.
+        assert_eq!(10, entry.read_at(&mut buf, 50).await.unwrap());
+        assert_eq!(&body[50..60], &buf);
+        assert_eq!(10, entry.read_at(&mut buf, 44).await.unwrap());
+        assert_eq!(&body[44..54], &buf);
+        assert!(matches!(
+            entry.read_at(&mut buf, 43).await,
+            Err(FileError(29, _))
+        ));
+
+        assert_eq!(5, entry.read_at(&mut buf, 95).await.unwrap());
+        assert_eq!(&body[95..], &buf[..5]);
+        assert_eq!(0, entry.read_at(&mut buf, 120).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn empty_index() {
+        let packages = "Package: zzuf-dbgsym
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+
+Package: zziplib-bin-dbgsym
+Build-Ids: 204d62991035324322317de6f71f494c06a10d37
+";
+
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        let stanzas = debug
+            .index(packages.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        assert_eq!(2, stanzas.total);
+        assert_eq!(0, stanzas.with_build_ids);
+        assert!(entries.is_empty());
+        assert!(debug.check_empty(&entries).is_ok());
+
+        let debug = debug.with_fail_on_empty_index(true);
+        assert!(matches!(debug.check_empty(&entries), Err(FileError(2, _))));
+    }
+
+    #[tokio::test]
+    async fn parallel_index() {
+        let packages: String = (0..500u32)
+            .map(|v| {
+                format!(
+                    "Package: pkg{v}-dbgsym\nBuild-Ids: {:02x}{v:038x} {:02x}{v:038x}\nFilename: pool/main/p/pkg{v}.deb\n\n",
+                    v % 251,
+                    v * 7 % 256,
+                )
+            })
+            .collect();
+
+        let listing = |entries: HashMap<String, (String, Vec<File>)>| {
+            let mut listing: Vec<_> = entries
+                .into_iter()
+                .map(|(dir, (_, files))| {
+                    let names: Vec<_> = files.iter().map(|v| v.name().to_owned()).collect();
+                    (dir, names)
+                })
+                .collect();
+            listing.sort();
+            listing
+        };
+
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut serial = HashMap::new();
+        let stanzas = debug
+            .index_with(packages.clone().into_bytes(), &mut serial, 1)
+            .await
+            .unwrap();
+        assert_eq!(500, stanzas.total);
+        let serial = listing(serial);
+        assert!(serial.len() > 100);
+
+        for workers in [2, 7, 64] {
+            let mut parallel = HashMap::new();
+            let stanzas = debug
+                .index_with(packages.clone().into_bytes(), &mut parallel, workers)
+                .await
+                .unwrap();
+            assert_eq!(500, stanzas.total, "{workers}");
+            assert_eq!(serial, listing(parallel), "{workers}");
+        }
+
+        // as it's decompressed, a few bytes at a time, in chunks big and
+        // small.
+        for chunk in [1, 1000, 1 << 20] {
+            let xz = testing::xz(packages.as_bytes());
+            let stream = Decompress::xz(Chunked(Cursor::new(xz)), Buffers::default())
+                .await
+                .unwrap();
+            let mut streamed = HashMap::new();
+            let stanzas = debug
+                .index_reader("Packages.xz", stream, &mut streamed, 3, chunk)
+                .await
+                .unwrap();
+            assert_eq!(500, stanzas.total, "{chunk}");
+            assert_eq!(serial, listing(streamed), "{chunk}");
+        }
+
+        // with `\r\n` line endings, it's still cut between stanzas.
+        assert_eq!(Some(8), stanza_end(b"A: 1\r\n\r\nB: 2\r\n"));
+        assert_eq!(Some(6), stanza_end(b"A: 1\n\nB: 2\n"));
+        assert_eq!(None, stanza_end(b"A: 1\r\nB: 2\r\n"));
+        let crlf = packages.replace('\n', "\r\n");
+        for chunk in [1, 1000, 1 << 20] {
+            let mut streamed = HashMap::new();
+            let stanzas = debug
+                .index_reader(
+                    "Packages",
+                    Chunked(Cursor::new(crlf.clone().into_bytes())),
+                    &mut streamed,
+                    3,
+                    chunk,
+                )
+                .await
+                .unwrap();
+            assert_eq!(500, stanzas.total, "{chunk}");
+            assert_eq!(serial, listing(streamed), "{chunk}");
+        }
+
+        let debug = debug.with_max_index_size(1000);
+        assert!(matches!(
+            debug
+                .index_reader(
+                    "Packages",
+                    Cursor::new(packages),
+                    &mut HashMap::new(),
+                    3,
+                    100
+                )
+                .await,
+            Err(FileError(27, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn index_field_case() {
+        let packages = b"Package: zzuf-dbgsym
+Build-IDs: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+";
+        let (stanzas, entries) =
+            index_chunk("http://127.0.0.1:1", &Extraction::default(), packages)
+                .await
+                .unwrap();
+        assert_eq!(1, stanzas.total);
+        let (_, files) = &entries["1c"];
+        assert_eq!(
+            vec![
+                "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+                "54e04fcf760c428d0afa79a33ffb8e068d35d5.deb",
+                "54e04fcf760c428d0afa79a33ffb8e068d35d5.package"
+            ],
+            files.iter().map(|v| v.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn ddeb() {
+        let debug_file = b"\x7fELF pretend this is a debug file".to_vec();
+        let ddeb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug_file[..],
+        )])
+        .await;
+        let packages = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2build1_amd64.ddeb
+";
+        let addr = Mirror::new(&[
+            (
+                "/dists/noble/main/debug/binary-amd64/Packages.xz",
+                testing::xz(packages.as_bytes()),
+            ),
+            (
+                "/pool/main/z/zzuf/zzuf-dbgsym_0.15-2build1_amd64.ddeb",
+                ddeb.clone(),
+            ),
+        ])
+        .listen_tcp()
+        .await;
+        let debug = Debug::new(&format!("http://{addr}"), "noble", "main", "amd64")
+            .with_layout(Arc::new(Ddebs));
+
+        let root = debug.attach("", "", 0).await.unwrap();
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        let Some(File::DebugHeader(dh)) = file else {
+            panic!("no debug file");
+        };
+        assert_eq!(debug_file, read_all(dh.open_cached().await.unwrap()).await);
+
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.ddeb"])
+            .await
+            .unwrap();
+        let Some(File::PoolDeb(pool_deb)) = file else {
+            panic!("no .ddeb file");
+        };
+        assert_eq!(ddeb, read_all(pool_deb.open_read().await.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn malformed_stanza() {
+        let mut packages = b"Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+
+Package: broken-dbgsym
+this line has no colon
+Build-Ids: 204d62991035324322317de6f71f494c06a10d37
+Filename: pool/main/b/broken/broken-dbgsym.deb
+
+Package: "
+            .to_vec();
+        packages.extend_from_slice(b"\xff\xfe-dbgsym\nBuild-Ids: 3a3a3a3a3a3a3a3a3a3a\n\n");
+        packages.extend_from_slice(
+            b"Package: zziplib-bin-dbgsym
+Build-Ids: 49a0ba466e7cea361ccb59d054ba9986a1ab7824
+Filename: pool/main/z/zziplib/zziplib-bin-dbgsym.deb
+",
+        );
+
+        let (stanzas, entries) =
+            index_chunk("http://127.0.0.1:1", &Extraction::default(), &packages)
+                .await
+                .unwrap();
+        assert_eq!(4, stanzas.total);
+        assert_eq!(2, stanzas.with_build_ids);
+        let mut dirs: Vec<_> = entries.keys().cloned().collect();
+        dirs.sort();
+        assert_eq!(vec!["1c", "49"], dirs);
+    }
+
+    #[tokio::test]
+    async fn package_file() {
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.package"])
+            .await
+            .unwrap();
+        let file = file.unwrap();
+        let expected =
+            b"Package: zzuf-dbgsym\nFilename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb\n";
+        assert_eq!(expected.len() as u64, file.size());
+        let (debug_file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        assert_ne!(debug_file.unwrap().qid_path(), file.qid_path());
+
+        let File::Package(package) = file else {
+            panic!("not a package file");
+        };
+        let text = read_all(OpenFile::Package(package.text)).await;
+        assert_eq!(&expected[..], &text[..]);
+    }
+
+    #[test]
+    fn pool_urls() {
+        for (root, filename) in [
+            ("http://deb.debian.org/debian-debug", "pool/main/z/zzuf.deb"),
+            (
+                "http://deb.debian.org/debian-debug/",
+                "pool/main/z/zzuf.deb",
+            ),
+            (
+                "http://deb.debian.org/debian-debug",
+                "/pool/main/z/zzuf.deb",
+            ),
+            (
+                "http://deb.debian.org/debian-debug//",
+                "//pool/main//z/zzuf.deb",
+            ),
+        ] {
+            assert_eq!(
+                "http://deb.debian.org/debian-debug/pool/main/z/zzuf.deb",
+                pool_url(root, filename),
+                "{root} {filename}"
+            );
+        }
+
+        for filename in [
+            "http://cdn.example.com/pool/main/z/zzuf.deb",
+            "HTTPS://cdn.example.com/pool/main/z/zzuf.deb",
+        ] {
+            assert_eq!(
+                filename,
+                pool_url("http://deb.debian.org/debian-debug", filename)
+            );
+        }
+        assert_eq!(
+            "file:///srv/mirror/pool/zzuf.deb",
+            pool_url("file:///srv/mirror/", "pool/zzuf.deb")
+        );
+    }
+
+    #[tokio::test]
+    async fn pool_root() {
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &b"\x7fELF pretend this is a debug file"[..],
+        )])
+        .await;
+        let packages = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/zzuf-dbgsym.deb
+";
+        let addr = Mirror::new(&[("/debian/pool/zzuf-dbgsym.deb", deb.clone())])
+            .listen_tcp()
+            .await;
+        // the index comes from somewhere else entirely.
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64")
+            .with_pool_root(&format!("http://{addr}/debian"));
+        let mut entries = HashMap::new();
+        debug
+            .index(packages.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.deb"])
+            .await
+            .unwrap();
+        let Some(File::PoolDeb(pool_deb)) = file else {
+            panic!("not a .deb");
+        };
+        assert_eq!(deb, read_all(pool_deb.open_read().await.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn pool_deb() {
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &b"\x7fELF pretend this is a debug file"[..],
+        )])
+        .await;
+        let size = deb.len();
+        let packages = format!(
+            "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5 204d62991035324322317de6f71f494c06a10d37
+Filename: pool/zzuf-dbgsym.deb
+Size: {}
+",
+            size + 1
+        );
+        let addr = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb.clone())])
+            .listen_tcp()
+            .await;
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(packages.into_bytes(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.deb"])
+            .await
+            .unwrap();
+        let file = file.unwrap();
+        // the index is wrong, which goes until the mirror says otherwise,
+        // and then it's the mirror that's believed for the stat.
+        assert_eq!(size as u64 + 1, file.size());
+        file.stat().await.unwrap();
+        assert_eq!(size as u64, file.size());
+        let File::PoolDeb(pool_deb) = &file else {
+            panic!("not a .deb");
+        };
+        assert!(matches!(pool_deb.open_read().await, Err(FileError(5, _))));
+
+        let (debug_file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        let (other, _) = root
+            .walk(&["20", "4d62991035324322317de6f71f494c06a10d37.deb"])
+            .await
+            .unwrap();
+        let qids: std::collections::HashSet<_> = [&file, &debug_file.unwrap(), &other.unwrap()]
+            .iter()
+            .map(|v| v.qid_path())
+            .collect();
+        assert_eq!(3, qids.len());
+
+        let File::PoolDeb(mut pool_deb) = file else {
+            panic!("not a .deb");
+        };
+        pool_deb.header.deb_size = None;
+        assert_eq!(deb, read_all(pool_deb.open_read().await.unwrap()).await);
+    }
+
+    #[tokio::test]
+    async fn walk_errors() {
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        // missing names are a short walk, wherever they are.
+        assert!(matches!(root.walk(&["zz"]).await, Ok((None, v)) if v.is_empty()));
+        assert!(matches!(root.walk(&["1c", "zz"]).await, Ok((None, v)) if v.len() == 1));
+
+        let (file, _) = root
+            .walk(&["1c", "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        let file = file.unwrap();
+        assert!(matches!(
+            file.walk(&["usr"]).await,
+            Err(FileError(20, name)) if name == "ENOTDIR"
+        ));
+        assert!(matches!(
+            root.walk(&[".build-ids", "usr"]).await,
+            Ok((None, v)) if v.len() == 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn duplicate_build_id() {
+        let packages = "Package: zzuf-dbgsym
+Version: 0.15-2+b10
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b10_amd64.deb
+
+Package: zzuf-dbgsym
+Version: 0.15-2+b4
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+
+Package: zzuf-dbgsym
+Version: 0.15-2+b11
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/main/z/zzuf/zzuf-dbgsym_0.15-2+b11_amd64.deb
+";
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(packages.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (dir, _) = root.walk(&["1c"]).await.unwrap();
+        let Some(File::Directory(dir)) = dir else {
+            panic!("no 1c directory");
+        };
+        assert_eq!(3, dir.entries.len());
+
+        let Some(File::DebugHeader(dh)) =
+            dir.lookup("54e04fcf760c428d0afa79a33ffb8e068d35d5.debug")
+        else {
+            panic!("no debug file");
+        };
+        assert!(dh.pool.ends_with("_0.15-2+b11_amd64.deb"), "{}", dh.pool);
+
+        let Some(File::Package(package)) =
+            dir.lookup("54e04fcf760c428d0afa79a33ffb8e068d35d5.package")
+        else {
+            panic!("no package file");
+        };
+        assert!(package.text.contains("Version: 0.15-2+b11\n"));
+
+        let Some(File::PoolDeb(deb)) = dir.lookup("54e04fcf760c428d0afa79a33ffb8e068d35d5.deb")
+        else {
+            panic!("no .deb file");
+        };
+        assert!(deb.header.pool.ends_with("_0.15-2+b11_amd64.deb"));
+    }
+
+    #[tokio::test]
+    async fn extract_over_unix_socket() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let header = debug_header(
+            &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+        );
+        let file = File::DebugHeader(header.clone());
+        assert_eq!(0, file.size());
+        match header.extract().await {
+            Ok(entry) => assert_eq!(debug, read_all(OpenFile::DebEntry(entry)).await),
+            _ => panic!("failed to extract the debug file"),
+        }
+        assert_eq!(debug.len() as u64, file.size());
+    }
+
+    #[tokio::test]
+    async fn stat_size() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let mirror = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]);
+        let socket = mirror.listen_unix();
+
+        let file = File::DebugHeader(DebugHeader {
+            stat_sizes: true,
+            ..debug_header(
+                &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            )
+        });
+        assert_eq!(0, file.size());
+        file.stat().await.unwrap();
+        assert_eq!(debug.len() as u64, file.size());
+
+        // known now, so the mirror is left alone.
+        let requests = mirror.requests().len();
+        file.stat().await.unwrap();
+        assert_eq!(requests, mirror.requests().len());
+    }
+
+    #[test]
+    fn directory_lookup() {
+        let dir = Directory::new(
+            "/",
+            0,
+            (0..=255u8)
+                .map(|v| File::Directory(Directory::new(&format!("{v:02x}"), 0, vec![])))
+                .collect(),
+        );
+        for v in 0..=255u8 {
+            let name = format!("{v:02x}");
+            assert_eq!(Some(name.as_str()), dir.lookup(&name).map(|v| v.name()));
+        }
+        assert!(dir.lookup("zz").is_none());
+    }
+
+    #[test]
+    fn qid_untrusted_names() {
+        let header = |build_id: &str| {
+            File::DebugHeader(debug_header(
+                "http://127.0.0.1:1/pool/zzuf-dbgsym.deb",
+                build_id,
+            ))
+        };
+        assert_eq!(
+            0x1c54e04fcf760c42,
+            header("1c54e04fcf760c428d0afa79a33ffb8e068d35d5").qid_path()
+        );
+        assert_eq!(header("zz54e04f").qid_path(), header("zz54e04f").qid_path());
+        assert_ne!(header("zz54e04f").qid_path(), header("zz54e04e").qid_path());
+
+        let dir = |name: &str| File::Directory(Directory::new(name, 1, vec![]));
+        assert_eq!(0x11c, dir("1c").qid_path());
+        assert_eq!(0x100, dir("zz").qid_path() & !0xff);
+    }
+
+    #[tokio::test]
+    async fn directory_sizes() {
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (dir, _) = root.walk(&["1c"]).await.unwrap();
+        let Some(File::Directory(dir)) = dir else {
+            panic!("no 1c directory");
+        };
+        let listing = read_dirents(OpenFile::Dirents(Dirents::new(dir.entries.clone()))).await;
+        assert_eq!(listing.len() as u64, File::Directory(dir).size());
+    }
+
+    #[tokio::test]
+    async fn directory_listing() {
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = Debug::root(entries);
+        let open = || OpenFile::Dirents(Dirents::new(Arc::new(root.entries())));
+
+        // every stat, one after another.
+        let mut expected = Cursor::new(vec![]);
+        for entry in root.entries() {
+            entry
+                .stat()
+                .await
+                .unwrap()
+                .dehydrate(&mut expected)
+                .unwrap();
+        }
+        let expected = expected.into_inner();
+        assert_eq!(expected, read_dirents(open()).await);
+
+        // starting part way through, without reading what's before it.
+        let ends: Vec<u64> = root
+            .entries()
+            .iter()
+            .scan(0, |end, entry| {
+                *end += dirent_len(entry);
+                Some(*end)
+            })
+            .collect();
+        for off in [0, ends[0], ends[ends.len() - 2], expected.len() as u64] {
+            let mut file = open();
+            let mut buf = vec![0u8; 4096];
+            let n = file.read_at(&mut buf, off).await.unwrap() as usize;
+            assert_eq!(&expected[off as usize..], &buf[..n], "{off}");
+        }
+        // anywhere else is in the middle of an entry.
+        for off in [1, ends[0] - 1, expected.len() as u64 - 1] {
+            assert!(
+                matches!(
+                    open().read_at(&mut [0u8; 4096], off).await,
+                    Err(FileError(22, _))
+                ),
+                "{off}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn directory_whole_entries() {
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        let root = Debug::root(entries);
+        let lens: Vec<u64> = root.entries().iter().map(dirent_len).collect();
+        let mut file = OpenFile::Dirents(Dirents::new(Arc::new(root.entries())));
+
+        // room for the first entry and most of the second only gets the
+        // first; the next read picks up at the second.
+        let mut buf = vec![0u8; (lens[0] + lens[1] - 1) as usize];
+        assert_eq!(lens[0], file.read_at(&mut buf, 0).await.unwrap() as u64);
+        assert_eq!(
+            lens[1],
+            file.read_at(&mut buf, lens[0]).await.unwrap() as u64
+        );
+
+        // not even room for one is an error, not a partial entry.
+        let mut buf = vec![0u8; lens[0] as usize - 1];
+        assert!(matches!(
+            file.read_at(&mut buf, 0).await,
+            Err(FileError(22, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn mtime() {
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &b"\x7fELF pretend this is a debug file"[..],
+        )])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let header = debug_header(
+            &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+        );
+        let dir = File::Directory(Directory::new(
+            "1c",
+            0,
+            vec![File::DebugHeader(header.clone())],
+        ));
+        assert_eq!(0, dir.mtime());
+
+        drop(header.extract().await.unwrap());
+        assert_eq!(testing::MTIME as u32, File::DebugHeader(header).mtime());
+        assert_eq!(testing::MTIME as u32, dir.mtime());
+    }
+
+    #[tokio::test]
+    async fn extract_checksum() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let sha256 = keyring::sha256(&deb);
+        let size = deb.len() as u64;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let header = |sha256: &str, size: u64| DebugHeader {
+            sha256: Some(sha256.to_owned()),
+            deb_size: Some(size),
+            ..debug_header(
+                &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            )
+        };
+
+        let entry = header(&sha256, size).extract().await.unwrap();
+        assert_eq!(debug, read_all(OpenFile::DebEntry(entry)).await);
+
+        assert!(matches!(
+            header(&sha256, size + 1).extract().await,
+            Err(FileError(5, _))
+        ));
+
+        // the contents come through, but the end of the file is an error.
+        let mut file = OpenFile::DebEntry(header(&"0".repeat(64), size).extract().await.unwrap());
+        let mut buf = [0u8; 7];
+        let mut off = 0;
+        let err = loop {
+            match file.read_at(&mut buf, off).await {
+                Ok(0) => panic!("corrupt .deb read to a clean EOF"),
+                Ok(n) => off += n as u64,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, FileError(5, _)));
+    }
+
+    #[test]
+    fn tar_paths() {
+        let want = "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug";
+        for path in [
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            "usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            "/usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            "./usr//lib/debug/./.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+        ] {
+            assert!(same_tar_path(std::path::Path::new(path), want), "{path}");
+        }
+        for path in [
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug/x",
+            "./lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            "./usr/lib/debug/.build-id/1c/../1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+        ] {
+            assert!(!same_tar_path(std::path::Path::new(path), want), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_without_dot() {
+        let deb = testing::deb(&[(
+            "usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &b"\x7fELF pretend this is a debug file"[..],
+        )])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let header = debug_header(
+            &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+        );
+        assert_eq!(
+            b"\x7fELF pretend this is a debug file".to_vec(),
+            read_all(header.open_read().await.unwrap()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn extract_missing() {
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/49/a0ba466e7cea361ccb59d054ba9986a1ab7824.debug",
+            &b"\x7fELF some other debug file"[..],
+        )])
+        .await;
+        let socket = Mirror::new(&[
+            ("/pool/zzuf-dbgsym.deb", deb),
+            ("/pool/not-a-deb.deb", b"!<arch>\n".to_vec()),
+        ])
+        .listen_unix();
+
+        let header = |pool: &str| {
+            debug_header(
+                &testing::unix_uri(&socket, pool),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            )
+        };
+
+        assert!(matches!(
+            header("/pool/zzuf-dbgsym.deb").extract().await,
+            Err(FileError(2, _))
+        ));
+        // no data.tar at all is a broken package, not a missing file.
+        assert!(matches!(
+            header("/pool/not-a-deb.deb").extract().await,
+            Err(FileError(5, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn extract_compressions() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let data = testing::tar(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+
+        for (name, compressed) in [
+            ("data.tar.zst", testing::zstd(&data).await),
+            ("data.tar.gz", testing::gzip(&data).await),
+            ("data.tar.bz2", testing::bzip2(&data).await),
+            ("data.tar.lzma", testing::lzma(&data).await),
+            ("data.tar", data.clone()),
+        ] {
+            let deb = testing::deb_member(name, &compressed).await;
+            let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+            let header = debug_header(
+                &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            );
+            match header.extract().await {
+                Ok(entry) => {
+                    assert_eq!(debug, read_all(OpenFile::DebEntry(entry)).await, "{name}")
+                }
+                _ => panic!("failed to extract the debug file from {name}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn strip() {
+        let full = testing::elf(&[
+            (".text", 1, &[0xcc; 4096][..]),
+            (".debug_info", 1, &b"pretend this is DWARF"[..]),
+        ]);
+        let stripped = elf::strip(&full).unwrap();
+        let deb = testing::deb(&[
+            (
+                "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+                &full[..],
+            ),
+            (
+                "./usr/lib/debug/.build-id/20/4d62991035324322317de6f71f494c06a10d37.debug",
+                &b"not an ELF at all"[..],
+            ),
+        ])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-strip", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Arc::new(DiskCache::new(&dir, 1 << 20).unwrap());
+        for (build_id, expected, cache) in [
+            (
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+                &stripped[..],
+                None,
+            ),
+            (
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+                &stripped[..],
+                Some(cache.clone()),
+            ),
+            (
+                "204d62991035324322317de6f71f494c06a10d37",
+                &b"not an ELF at all"[..],
+                None,
+            ),
+        ] {
+            let header = DebugHeader {
+                cache,
+                strip: true,
+                ..debug_header(
+                    &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                    build_id,
+                )
+            };
+            let file = header.open_cached().await.unwrap();
+            assert_eq!(expected, &read_all(file).await[..], "{build_id}");
+            assert_eq!(Some(expected.len() as u64), header.size.get().copied());
+        }
+
+        // cached apart from the whole thing.
+        assert!(cache
+            .get("1c54e04fcf760c428d0afa79a33ffb8e068d35d5")
+            .await
+            .is_none());
+        assert!(cache
+            .get("1c54e04fcf760c428d0afa79a33ffb8e068d35d5.stripped")
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn wrong_build_id() {
+        let build_id = "1c54e04fcf760c428d0afa79a33ffb8e068d35d5";
+        let mut note = vec![];
+        for v in [4u32, 20, 3] {
+            note.extend_from_slice(&v.to_le_bytes());
+        }
+        note.extend_from_slice(b"GNU\0");
+        for idx in (0..build_id.len()).step_by(2) {
+            note.push(u8::from_str_radix(&build_id[idx..idx + 2], 16).unwrap());
+        }
+        let debug = testing::elf(&[(".note.gnu.build-id", 7, &note[..])]);
+        let deb = testing::deb(&[
+            (
+                "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+                &debug[..],
+            ),
+            (
+                "./usr/lib/debug/.build-id/20/4d62991035324322317de6f71f494c06a10d37.debug",
+                &debug[..],
+            ),
+        ])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-build-id", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Arc::new(DiskCache::new(&dir, 1 << 20).unwrap());
+        for (build_id, strip, ok) in [
+            ("1c54e04fcf760c428d0afa79a33ffb8e068d35d5", false, true),
+            ("1c54e04fcf760c428d0afa79a33ffb8e068d35d5", true, true),
+            ("204d62991035324322317de6f71f494c06a10d37", false, false),
+            ("204d62991035324322317de6f71f494c06a10d37", true, false),
+        ] {
+            let header = DebugHeader {
+                cache: Some(cache.clone()),
+                strip,
+                ..debug_header(
+                    &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                    build_id,
+                )
+            };
+            match header.open_cached().await {
+                Ok(_) => assert!(ok, "{build_id} {strip}"),
+                Err(FileError(5, _)) => assert!(!ok, "{build_id} {strip}"),
+                Err(err) => panic!("{build_id} {strip}: {err:?}"),
+            }
+            assert_eq!(ok, cache.get(&header.cache_key()).await.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_truncated() {
+        // something xz can't squeeze down to nothing, so that cutting the
+        // stream in half cuts into the debug file.
+        let mut state = 1u32;
+        let debug: Vec<u8> = (0..256 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let data = testing::xz(
+            &testing::tar(&[(
+                "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+                &debug[..],
+            )])
+            .await,
+        );
+        let deb = testing::deb_member("data.tar.xz", &data).await;
+        let socket = Mirror::new(&[
+            // the ar member is whole, but the xz stream in it isn't.
+            (
+                "/pool/cut-stream.deb",
+                testing::deb_member("data.tar.xz", &data[..data.len() / 2]).await,
+            ),
+            // the mirror only has the first half of the file.
+            ("/pool/cut-deb.deb", deb[..deb.len() / 2].to_vec()),
+        ])
+        .listen_unix();
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-truncated", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Arc::new(DiskCache::new(&dir, 1 << 20).unwrap());
+        for pool in ["/pool/cut-stream.deb", "/pool/cut-deb.deb"] {
+            for cache in [None, Some(cache.clone())] {
+                let header = DebugHeader {
+                    cache,
+                    ..debug_header(
+                        &testing::unix_uri(&socket, pool),
+                        "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+                    )
+                };
+                // without a cache, the file streams, and it's reading it
+                // that finds the stream cut short.
+                let res = match (&header.cache, header.open_read().await) {
+                    (None, Ok(mut file)) => {
+                        let mut buf = vec![0u8; 64 * 1024];
+                        let mut off = 0;
+                        loop {
+                            match file.read_at(&mut buf, off).await {
+                                Ok(0) => break Ok(()),
+                                Ok(n) => off += n as u64,
+                                Err(err) => break Err(err),
+                            }
+                        }
+                    }
+                    (_, res) => res.map(drop),
+                };
+                assert!(
+                    matches!(res, Err(FileError(61, name)) if name == "ENODATA"),
+                    "{pool}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_unsupported() {
+        let deb = testing::deb_member("data.tar.lz4", b"not really lz4").await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let header = debug_header(
+            &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+        );
+        assert!(matches!(
+            header.extract().await,
+            Err(FileError(95, name)) if name == "EOPNOTSUPP"
+        ));
+    }
+
+    #[tokio::test]
+    async fn extract_cached() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let mirror = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]);
+        let socket = mirror.listen_unix();
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-extract", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut header = DebugHeader {
+            cache: Some(Arc::new(DiskCache::new(&dir, 1 << 20).unwrap())),
+            ..debug_header(
+                &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            )
+        };
+
+        let file = header.open_cached().await.unwrap();
+        assert!(matches!(file, OpenFile::Disk(_)));
+        assert_eq!(debug, read_all(file).await);
+        let requests = mirror.requests().len();
+
+        // the second open never touches the mirror.
+        header.pool = "http://127.0.0.1:1/pool/zzuf-dbgsym.deb".to_owned();
+        let file = header.open_cached().await.unwrap();
+        assert_eq!(debug, read_all(file).await);
+        assert_eq!(requests, mirror.requests().len());
+    }
+
+    #[tokio::test]
+    async fn extract_compressed() {
+        // bigger than a DebEntry holds on to, to read back past it.
+        let debug: Vec<u8> = (0..3 * DEB_ENTRY_WINDOW as u32)
+            .map(|v| (v % 251) as u8)
+            .collect();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-compressed", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = DiskCache::new(&dir, 16 << 20)
+            .unwrap()
+            .with_compression(true);
+        let header = DebugHeader {
+            cache: Some(Arc::new(cache)),
+            ..debug_header(
+                &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            )
+        };
+
+        for _ in 0..2 {
+            let mut file = header.open_cached().await.unwrap();
+            assert!(matches!(file, OpenFile::Compressed(_)));
+            assert_eq!(Some(&(debug.len() as u64)), header.size.get());
+
+            let mut buf = vec![0u8; 4096];
+            for off in [2 * DEB_ENTRY_WINDOW, 0, debug.len() - 10] {
+                let n = file.read_at(&mut buf, off as u64).await.unwrap() as usize;
+                assert_eq!(&debug[off..off + n], &buf[..n], "{off}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesce_opens() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-coalesce", std::process::id()));
+        let mut requests = vec![];
+        for opens in [1, 4] {
+            let _ = std::fs::remove_dir_all(&dir);
+            let mirror = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb.clone())]);
+            let socket = mirror.listen_unix();
+            let header = DebugHeader {
+                cache: Some(Arc::new(DiskCache::new(&dir, 1 << 20).unwrap())),
+                ..debug_header(
+                    &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                    "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+                )
+            };
+
+            let files = futures::future::join_all((0..opens).map(|_| header.open_cached())).await;
+            for file in files {
+                assert_eq!(debug, read_all(file.unwrap()).await);
+            }
+            assert!(header.inflight.0.lock().unwrap().is_empty());
+            requests.push(mirror.requests().len());
+        }
+        // four opens at once cost the mirror no more than one.
+        assert_eq!(requests[0], requests[1]);
+    }
+
+    #[tokio::test]
+    async fn max_fetches() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let socket = Mirror::new(&[("/pool/zzuf-dbgsym.deb", deb)]).listen_unix();
+
+        let fetches = Arc::new(Semaphore::new(1));
+        let header = DebugHeader {
+            fetches: Some(fetches.clone()),
+            ..debug_header(
+                &testing::unix_uri(&socket, "/pool/zzuf-dbgsym.deb"),
+                "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            )
+        };
+
+        // with the only permit taken, the open waits rather than failing.
+        let held = fetches.clone().acquire_owned().await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), header.open_cached())
+                .await
+                .is_err()
+        );
+
+        // the open streams the file, keeping the turn until it's been
+        // read to the end, not until the file is closed.
+        drop(held);
+        let mut file = header.open_cached().await.unwrap();
+        assert_eq!(0, fetches.available_permits());
+        let mut body = vec![];
+        let mut buf = [0u8; 7];
+        loop {
+            let n = file.read_at(&mut buf, body.len() as u64).await.unwrap() as usize;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(debug, body);
+        assert_eq!(1, fetches.available_permits());
+
+        // one that's closed before it's been read gives it back too.
+        let next = tokio::time::timeout(Duration::from_secs(5), header.open_cached())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(0, fetches.available_permits());
+        drop(next);
+        assert_eq!(1, fetches.available_permits());
+        drop(file);
+    }
+
+    #[tokio::test]
+    async fn prefetch() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let packages = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/zzuf-dbgsym.deb
+
+Package: zziplib-bin-dbgsym
+Build-Ids: 49a0ba466e7cea361ccb59d054ba9986a1ab7824
+Filename: pool/zziplib-bin-dbgsym.deb
+
+Package: zsh-dbgsym
+Build-Ids: 204d62991035324322317de6f71f494c06a10d37
+Filename: pool/zsh-dbgsym.deb
+";
+        let mut files = vec![(
+            "/dists/unstable-debug/main/binary-amd64/Packages.xz".to_owned(),
+            testing::xz(packages.as_bytes()),
+        )];
+        for (package, build_id) in [
+            ("zzuf-dbgsym", "1c54e04fcf760c428d0afa79a33ffb8e068d35d5"),
+            (
+                "zziplib-bin-dbgsym",
+                "49a0ba466e7cea361ccb59d054ba9986a1ab7824",
+            ),
+            ("zsh-dbgsym", "204d62991035324322317de6f71f494c06a10d37"),
+        ] {
+            let path = format!(
+                "./usr/lib/debug/.build-id/{}/{}.debug",
+                &build_id[..2],
+                &build_id[2..]
+            );
+            let deb = testing::deb(&[(&path, &debug[..])]).await;
+            files.push((format!("/pool/{package}.deb"), deb));
+        }
+        let files: Vec<_> = files.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+        let mirror = Mirror::new(&files);
+        let addr = mirror.listen_tcp().await;
+
+        let dir = std::env::temp_dir().join(format!("debugfs-{}-prefetch", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = Arc::new(DiskCache::new(&dir, 1 << 20).unwrap());
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_cache(cache.clone());
+
+        let wanted = vec![
+            "zzuf-dbgsym".to_owned(),
+            "49a0ba466e7cea361ccb59d054ba9986a1ab7824".to_owned(),
+            "not-a-package".to_owned(),
+        ];
+        assert_eq!(2, debug.handle().prefetch(&wanted).await.unwrap());
+        assert!(cache
+            .get("1c54e04fcf760c428d0afa79a33ffb8e068d35d5")
+            .await
+            .is_some());
+        assert!(cache
+            .get("204d62991035324322317de6f71f494c06a10d37")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn max_sessions() {
+        let addr = Mirror::new(&[(
+            "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+            testing::xz(PACKAGES.as_bytes()),
+        )])
+        .listen_tcp()
+        .await;
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_max_sessions(1);
+
+        let first = debug.attach("", "", 0).await.unwrap();
+        assert!(matches!(
+            debug.attach("", "", 0).await,
+            Err(FileError(11, _))
+        ));
+
+        drop(first);
+        assert!(debug.attach("", "", 0).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn build_ids_listing() {
+        let packages = format!(
+            "{PACKAGES}
+
+Package: zziplib-bin-dbgsym
+Build-Ids: 204d62991035324322317de6f71f494c06a10d37 23c08beddf41e0098035f3c34274450ccc0a9f21
+Filename: pool/main/z/zziplib/zziplib-bin-dbgsym_0.13.72+dfsg.1-1.2_amd64.deb
+"
+        );
+
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(packages.into_bytes(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (file, _) = root.walk(&[".build-ids"]).await.unwrap();
+        let Some(File::BuildIds(root)) = file else {
+            panic!("no .build-ids file");
+        };
+
+        // read in small, uneven chunks to cross line boundaries.
+        let mut listing = vec![];
+        loop {
+            let mut buf = [0u8; 7];
+            let n = root.read_build_ids(&mut buf, listing.len() as u64);
+            if n == 0 {
+                break;
+            }
+            listing.extend_from_slice(&buf[..n]);
+        }
+
+        assert_eq!(
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+204d62991035324322317de6f71f494c06a10d37
+23c08beddf41e0098035f3c34274450ccc0a9f21
+49a0ba466e7cea361ccb59d054ba9986a1ab7824
+",
+            String::from_utf8(listing).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn index_tsv() {
+        let packages = format!(
+            "{PACKAGES}
+
+Build-Ids: 204d62991035324322317de6f71f494c06a10d37
+Filename: pool/main/z/zziplib/zziplib-bin-dbgsym_0.13.72+dfsg.1-1.2_amd64.deb
+"
+        );
+
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64");
+        let mut entries = HashMap::new();
+        debug
+            .index(packages.into_bytes(), &mut entries)
+            .await
+            .unwrap();
+        let root = File::Root(Debug::root(entries));
+
+        let (file, _) = root.walk(&["index.tsv"]).await.unwrap();
+        let Some(File::Index(index)) = file else {
+            panic!("no index.tsv file");
+        };
+        let expected = "\
+1c54e04fcf760c428d0afa79a33ffb8e068d35d5\tzzuf-dbgsym\tpool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+204d62991035324322317de6f71f494c06a10d37\t\tpool/main/z/zziplib/zziplib-bin-dbgsym_0.13.72+dfsg.1-1.2_amd64.deb
+49a0ba466e7cea361ccb59d054ba9986a1ab7824\tzzuf-dbgsym\tpool/main/z/zzuf/zzuf-dbgsym_0.15-2+b4_amd64.deb
+";
+        assert_eq!(expected.len() as u64, File::Index(index.clone()).size());
+        let listing = read_all(OpenFile::Index(index)).await;
+        assert_eq!(expected, String::from_utf8(listing).unwrap());
+    }
+
+    #[tokio::test]
+    async fn serve_stale() {
+        // nothing listens on port 1, so every refresh fails.
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64")
+            .with_index_ttl(Duration::ZERO);
+        assert!(debug.attach("", "", 0).await.is_err());
+
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        *debug.cached.lock().unwrap() = Some(Cached {
+            loaded: Instant::now(),
+            attempted: Instant::now(),
+            root: Debug::root(entries),
+        });
+
+        let root = debug.attach("", "", 0).await.unwrap();
+        let (dir, _) = root.walk(&["1c"]).await.unwrap();
+        let (file, _) = dir
+            .unwrap()
+            .walk(&["54e04fcf760c428d0afa79a33ffb8e068d35d5.debug"])
+            .await
+            .unwrap();
+        assert!(matches!(file, Some(File::DebugHeader(_))));
+
+        let debug = debug.with_serve_stale(false);
+        assert!(debug.attach("", "", 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stale_backoff() {
+        // nothing to be had, so every refresh fails.
+        let mirror = Mirror::new(&[]);
+        let addr = mirror.listen_tcp().await;
+        let ttl = Duration::from_millis(200);
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_index_ttl(ttl);
+
+        let mut entries = HashMap::new();
+        debug
+            .index(PACKAGES.as_bytes().to_vec(), &mut entries)
+            .await
+            .unwrap();
+        *debug.cached.lock().unwrap() = Some(Cached {
+            loaded: Instant::now(),
+            attempted: Instant::now(),
+            root: Debug::root(entries),
+        });
+        tokio::time::sleep(ttl).await;
+
+        // one failed refresh, and then the stale index is served as-is
+        // until the TTL is up again.
+        debug.attach("", "", 0).await.unwrap();
+        let requests = mirror.requests().len();
+        assert!(requests > 0);
+        debug.attach("", "", 0).await.unwrap();
+        assert_eq!(requests, mirror.requests().len());
+
+        // attaches all at once wait on the one refresh.
+        tokio::time::sleep(ttl).await;
+        let attaches = futures::future::join_all((0..4).map(|_| debug.attach("", "", 0))).await;
+        assert!(attaches.iter().all(|root| root.is_ok()));
+        assert_eq!(2 * requests, mirror.requests().len());
+    }
+
+    #[tokio::test]
+    async fn index_ttl() {
+        let mirror = Mirror::new(&[(
+            "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+            testing::xz(PACKAGES.as_bytes()),
+        )]);
+        let addr = mirror.listen_tcp().await;
+        let archive_root = format!("http://{addr}");
+
+        let debug = Debug::new(&archive_root, "unstable-debug", "main", "amd64");
+        debug.attach("", "", 0).await.unwrap();
+        debug.attach("", "", 0).await.unwrap();
+        assert_eq!(1, mirror.requests().len());
+
+        let debug = Debug::new(&archive_root, "unstable-debug", "main", "amd64")
+            .with_index_ttl(Duration::ZERO);
+        debug.attach("", "", 0).await.unwrap();
+        debug.attach("", "", 0).await.unwrap();
+        assert_eq!(3, mirror.requests().len());
+    }
+
+    #[tokio::test]
+    async fn arch_dirs() {
+        let addr = Mirror::new(&[
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+            (
+                "/dists/unstable-debug/main/binary-arm64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+        ])
+        .listen_tcp()
+        .await;
+        let debug = Debug::discover(&format!("http://{addr}"), "unstable-debug")
+            .with_components(&["main"])
+            .with_arches(&["amd64", "arm64"])
+            .with_arch_dirs(true);
+
+        let root = debug.attach("", "", 0).await.unwrap();
+        assert!(matches!(
+            root.walk(&[".build-ids"]).await.unwrap(),
+            (None, _)
+        ));
+
+        let mut qids = vec![];
+        for arch in ["amd64", "arm64"] {
+            let (file, _) = root
+                .walk(&[
+                    arch,
+                    ".build-id",
+                    "1c",
+                    "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+                ])
+                .await
+                .unwrap();
+            assert!(matches!(file, Some(File::DebugHeader(_))), "{arch}");
+
+            for path in [
+                &[arch][..],
+                &[arch, ".build-id"],
+                &[arch, ".build-id", "1c"],
+                &[arch, ".build-id", ".build-ids"],
+                &[arch, ".build-id", "index.tsv"],
+            ] {
+                let (file, _) = root.walk(path).await.unwrap();
+                qids.push(file.unwrap().qid_path());
+            }
+        }
+        qids.push(root.qid_path());
+        let unique: std::collections::HashSet<_> = qids.iter().collect();
+        assert_eq!(qids.len(), unique.len());
+    }
+
+    #[tokio::test]
+    async fn suites() {
+        let mirror = Mirror::new(&[
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+            (
+                "/dists/bookworm-debug/main/binary-amd64/Packages.xz",
+                testing::xz(PACKAGES.as_bytes()),
+            ),
+        ]);
+        let addr = mirror.listen_tcp().await;
+        let debug = Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64")
+            .with_suites(&["bookworm-debug"]);
+
+        let root = debug.attach("", "", 0).await.unwrap();
+        assert_eq!(0, mirror.requests().len());
+
+        let mut qids = vec![root.qid_path()];
+        for (n, suite) in ["unstable-debug", "bookworm-debug"].into_iter().enumerate() {
+            let (file, _) = root
+                .walk(&[
+                    suite,
+                    ".build-id",
+                    "1c",
+                    "54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+                ])
+                .await
+                .unwrap();
+            assert!(matches!(file, Some(File::DebugHeader(_))), "{suite}");
+            assert_eq!(n + 1, mirror.requests().len());
+
+            for path in [
+                &[suite][..],
+                &[suite, ".build-id"],
+                &[suite, ".build-id", "1c"],
+            ] {
+                let (file, _) = root.walk(path).await.unwrap();
+                qids.push(file.unwrap().qid_path());
+            }
+        }
+        let unique: std::collections::HashSet<_> = qids.iter().collect();
+        assert_eq!(qids.len(), unique.len());
+    }
+
+    #[tokio::test]
+    async fn attach_anames() {
+        // nothing listens on port 1, so an allowed attach fails to fetch.
+        let debug = Debug::new("http://127.0.0.1:1", "unstable-debug", "main", "amd64")
+            .with_anames(&["unstable-amd64"]);
+
+        assert!(matches!(
+            debug.attach("bookworm-amd64", "", 0).await,
+            Err(FileError(13, _))
+        ));
+        assert!(matches!(
+            debug.attach("", "", 0).await,
+            Err(FileError(2, _))
+        ));
+        assert!(matches!(
+            debug.attach("unstable-amd64", "", 0).await,
+            Err(FileError(121, _))
+        ));
+
+        let debug = debug.with_default_aname("unstable-amd64");
+        assert!(matches!(
+            debug.attach("", "", 0).await,
+            Err(FileError(121, _))
+        ));
     }
 }
 
@@ -0,0 +1,216 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use super::debugfs::Debug;
+use arigato::server::{FileError, OpenFile as OpenFileTrait};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Empty, StreamBody};
+use hyper::{
+    body::{Bytes, Frame, Incoming},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response,
+};
+use hyper_util::rt::TokioIo;
+use std::{convert::Infallible, sync::Arc};
+use tokio::net::TcpListener;
+
+/// How much of a debug file to hand to the client at a time.
+const CHUNK: usize = 64 * 1024;
+
+/// Answer debuginfod's `GET /buildid/<build-id>/debuginfo` on `listener`
+/// out of the first of `filesystems` to have the build-id. We don't have
+/// executables or sources, so asking for those is a 404, like anything
+/// else.
+pub async fn serve(listener: TcpListener, filesystems: Arc<Vec<Debug>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let filesystems = filesystems.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let filesystems = filesystems.clone();
+                async move { Ok::<_, Infallible>(respond(req, &filesystems).await) }
+            });
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("debuginfod client went away: {:?}", err);
+            }
+        });
+    }
+}
+
+/// Body of a response, which fails if the debug file can't be read all
+/// the way through.
+type Body = UnsyncBoxBody<Bytes, std::io::Error>;
+
+/// A response with no body.
+fn status(status: u16) -> Response<Body> {
+    let body = Empty::new().map_err(|never| match never {});
+    Response::builder()
+        .status(status)
+        .body(body.boxed_unsync())
+        .unwrap()
+}
+
+///
+async fn respond(req: Request<Incoming>, filesystems: &[Debug]) -> Response<Body> {
+    let build_id = match req
+        .uri()
+        .path()
+        .strip_prefix("/buildid/")
+        .and_then(|v| v.strip_suffix("/debuginfo"))
+    {
+        Some(v) if req.method() == Method::GET => v,
+        _ => return status(404),
+    };
+
+    // a filesystem whose index won't load shouldn't stop the others from
+    // answering, but if none of them do, it might have been there.
+    let (mut header, mut failed) = (None, false);
+    for debug in filesystems {
+        match debug.lookup(build_id).await {
+            Ok(None) => continue,
+            Ok(Some(v)) => {
+                header = Some(v);
+                break;
+            }
+            Err(FileError(_, name)) => {
+                tracing::warn!("couldn't look up {}: {}", build_id, name);
+                failed = true;
+            }
+        }
+    }
+    let header = match (header, failed) {
+        (Some(v), _) => v,
+        (None, true) => return status(502),
+        (None, false) => return status(404),
+    };
+    let file = match header.open_read().await {
+        Ok(v) => v,
+        Err(FileError(2, _)) => return status(404),
+        Err(_) => return status(502),
+    };
+
+    let chunks = futures::stream::try_unfold((file, 0), |(mut file, off)| async move {
+        let mut buf = vec![0u8; CHUNK];
+        let n = file
+            .read_at(&mut buf, off)
+            .await
+            .map_err(|FileError(_, name)| std::io::Error::other(name))? as usize;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        Ok(Some((
+            Frame::data(Bytes::from(buf)),
+            (file, off + n as u64),
+        )))
+    });
+
+    // without a size, the body is sent chunked.
+    let mut response = Response::builder().header("content-type", "application/octet-stream");
+    if let Some(size) = header.known_size() {
+        response = response.header("content-length", size);
+    }
+    response
+        .body(StreamBody::new(chunks).boxed_unsync())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::serve;
+    use crate::{debugfs::Debug, testing, testing::Mirror};
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[tokio::test]
+    async fn debuginfo() {
+        let debug = b"\x7fELF pretend this is a debug file".to_vec();
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &debug[..],
+        )])
+        .await;
+        let packages = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/zzuf-dbgsym.deb
+";
+        let addr = Mirror::new(&[
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(packages.as_bytes()),
+            ),
+            ("/pool/zzuf-dbgsym.deb", deb),
+        ])
+        .listen_tcp()
+        .await;
+        // arm64's index isn't there, which only matters for build-ids
+        // amd64 doesn't have.
+        let filesystems = vec![
+            Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "arm64"),
+            Debug::new(&format!("http://{addr}"), "unstable-debug", "main", "amd64"),
+        ];
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let debuginfod = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(filesystems)));
+
+        for (path, status) in [
+            (
+                "/buildid/1C54E04FCF760C428D0AFA79A33FFB8E068D35D5/debuginfo",
+                "200",
+            ),
+            (
+                "/buildid/1c54e04fcf760c428d0afa79a33ffb8e068d35d5/executable",
+                "404",
+            ),
+            (
+                "/buildid/204d62991035324322317de6f71f494c06a10d37/debuginfo",
+                "502",
+            ),
+            ("/buildid/not-hex/debuginfo", "404"),
+        ] {
+            let mut stream = TcpStream::connect(debuginfod).await.unwrap();
+            stream
+                .write_all(
+                    format!("GET {path} HTTP/1.1\r\nhost: debugfs\r\nconnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let mut response = vec![];
+            stream.read_to_end(&mut response).await.unwrap();
+            let response = String::from_utf8_lossy(&response);
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {status} ")),
+                "{path}: {response}"
+            );
+            if status == "200" {
+                assert!(response.ends_with("\r\n\r\n\x7fELF pretend this is a debug file"));
+            }
+        }
+    }
+}
+
+// vim: foldmethod=marker
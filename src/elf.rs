@@ -0,0 +1,525 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+///
+const MAGIC: [u8; 4] = *b"\x7fELF";
+
+///
+const SHT_SYMTAB: u32 = 2;
+
+///
+const SHT_STRTAB: u32 = 3;
+
+///
+const SHT_NOTE: u32 = 7;
+
+///
+const SHT_NOBITS: u32 = 8;
+
+///
+const NT_GNU_BUILD_ID: u32 = 3;
+
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(&self, buf: &[u8], off: usize) -> Option<u16> {
+        let raw: [u8; 2] = buf.get(off..off.checked_add(2)?)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u16::from_le_bytes(raw),
+            Self::Big => u16::from_be_bytes(raw),
+        })
+    }
+
+    fn u32(&self, buf: &[u8], off: usize) -> Option<u32> {
+        let raw: [u8; 4] = buf.get(off..off.checked_add(4)?)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u32::from_le_bytes(raw),
+            Self::Big => u32::from_be_bytes(raw),
+        })
+    }
+
+    fn u64(&self, buf: &[u8], off: usize) -> Option<u64> {
+        let raw: [u8; 8] = buf.get(off..off.checked_add(8)?)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u64::from_le_bytes(raw),
+            Self::Big => u64::from_be_bytes(raw),
+        })
+    }
+
+    /// Write `v` into `buf` at `off` as a `width` byte field.
+    fn put(&self, buf: &mut [u8], off: usize, v: u64, width: usize) {
+        let bytes = match self {
+            Self::Little => v.to_le_bytes(),
+            Self::Big => v.to_be_bytes(),
+        };
+        let bytes = match self {
+            Self::Little => &bytes[..width],
+            Self::Big => &bytes[8 - width..],
+        };
+        buf[off..off + width].copy_from_slice(bytes);
+    }
+}
+
+/// Section header fields we care about, normalized across ELF classes.
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    align: usize,
+}
+
+impl Section {
+    ///
+    fn data<'a>(&self, elf: &'a [u8]) -> Option<&'a [u8]> {
+        elf.get(self.offset..self.offset.checked_add(self.size)?)
+    }
+}
+
+/// The ELF header fields we care about, normalized across ELF classes.
+struct Header {
+    class64: bool,
+    endian: Endian,
+    ehsize: usize,
+    shoff: usize,
+    shentsize: usize,
+    shnum: usize,
+    shstrndx: usize,
+}
+
+impl Header {
+    ///
+    fn parse(elf: &[u8]) -> Option<Self> {
+        if elf.get(..4)? != MAGIC {
+            return None;
+        }
+
+        let class64 = match elf.get(4)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        };
+        let endian = match elf.get(5)? {
+            1 => Endian::Little,
+            2 => Endian::Big,
+            _ => return None,
+        };
+
+        let (shoff, ehsize, shentsize, shnum, shstrndx) = if class64 {
+            (
+                endian.u64(elf, 0x28)?,
+                endian.u16(elf, 0x34)?,
+                endian.u16(elf, 0x3A)?,
+                endian.u16(elf, 0x3C)?,
+                endian.u16(elf, 0x3E)?,
+            )
+        } else {
+            (
+                endian.u32(elf, 0x20)? as u64,
+                endian.u16(elf, 0x28)?,
+                endian.u16(elf, 0x2E)?,
+                endian.u16(elf, 0x30)?,
+                endian.u16(elf, 0x32)?,
+            )
+        };
+        Some(Header {
+            class64,
+            endian,
+            ehsize: ehsize as usize,
+            shoff: shoff.try_into().ok()?,
+            shentsize: shentsize as usize,
+            shnum: shnum as usize,
+            shstrndx: shstrndx as usize,
+        })
+    }
+
+    /// Offset of the `idx`th section header.
+    fn section_offset(&self, idx: usize) -> Option<usize> {
+        self.shoff.checked_add(idx.checked_mul(self.shentsize)?)
+    }
+
+    ///
+    fn section(&self, elf: &[u8], idx: usize) -> Option<Section> {
+        let off = self.section_offset(idx)?;
+        let endian = self.endian;
+        Some(if self.class64 {
+            Section {
+                name: endian.u32(elf, off)?,
+                kind: endian.u32(elf, off + 4)?,
+                offset: endian.u64(elf, off + 24)?.try_into().ok()?,
+                size: endian.u64(elf, off + 32)?.try_into().ok()?,
+                align: endian.u64(elf, off + 48)?.try_into().ok()?,
+            }
+        } else {
+            Section {
+                name: endian.u32(elf, off)?,
+                kind: endian.u32(elf, off + 4)?,
+                offset: endian.u32(elf, off + 16)? as usize,
+                size: endian.u32(elf, off + 20)? as usize,
+                align: endian.u32(elf, off + 32)? as usize,
+            }
+        })
+    }
+}
+
+/// Return the GNU build-id embedded in an ELF file, if there is one. This
+/// handles either class and byte order, since we serve debug files for
+/// architectures other than the one we're running on.
+pub fn build_id(elf: &[u8]) -> Option<&[u8]> {
+    let header = Header::parse(elf)?;
+    let endian = header.endian;
+
+    for idx in 0..header.shnum {
+        let section = header.section(elf, idx)?;
+        if section.kind != SHT_NOTE {
+            continue;
+        }
+        let notes = match section.data(elf) {
+            None => continue,
+            Some(v) => v,
+        };
+        if let Some(id) = section_build_id(notes, endian, section.align) {
+            return Some(id);
+        }
+    }
+
+    None
+}
+
+/// Like [build_id], but reading only the headers and notes out of `file`
+/// rather than needing all of it in memory.
+pub fn build_id_at(file: &std::fs::File) -> Option<Vec<u8>> {
+    use std::os::unix::fs::FileExt;
+    let read = |offset: usize, len: usize| {
+        let mut buf = vec![0u8; len];
+        file.read_exact_at(&mut buf, offset as u64).ok()?;
+        Some(buf)
+    };
+
+    let header = Header::parse(&read(0, 64)?)?;
+    let table = read(header.shoff, header.shnum.checked_mul(header.shentsize)?)?;
+    // the table's read on its own, so it starts at 0.
+    let table_header = Header { shoff: 0, ..header };
+
+    for idx in 0..table_header.shnum {
+        let section = table_header.section(&table, idx)?;
+        if section.kind != SHT_NOTE {
+            continue;
+        }
+        let notes = match read(section.offset, section.size) {
+            None => continue,
+            Some(v) => v,
+        };
+        if let Some(id) = section_build_id(&notes, table_header.endian, section.align) {
+            return Some(id.to_vec());
+        }
+    }
+
+    None
+}
+
+/// The build-id in a `SHT_NOTE` section aligned to `align`, if it has one.
+fn section_build_id(notes: &[u8], endian: Endian, align: usize) -> Option<&[u8]> {
+    // Most notes are 4-byte aligned regardless of class, but some
+    // tooling emits 8-byte aligned notes; trust the section first and
+    // fall back to the other layout.
+    let (first, second) = if align == 8 { (8, 4) } else { (4, 8) };
+    gnu_build_id(notes, endian, first).or_else(|| gnu_build_id(notes, endian, second))
+}
+
+/// Whether gdb wants the contents of a section out of a separate debug
+/// file: the DWARF itself, the notes carrying the build-id, and the symbol
+/// and string tables everything else points into.
+fn keep(kind: u32, name: &[u8]) -> bool {
+    matches!(kind, SHT_NOTE | SHT_SYMTAB | SHT_STRTAB)
+        || name.starts_with(b".debug_")
+        || name.starts_with(b".zdebug_")
+        || name == b".gnu_debugaltlink"
+        || name == b".gdb_index"
+}
+
+/// Rewrite `elf` with only the sections a debugger reads out of a separate
+/// debug file, the way `objcopy --only-keep-debug` does: every section
+/// header stays, so indexes into the table still hold, but the ones we
+/// don't want become `SHT_NOBITS` with nothing behind them. Program headers
+/// are dropped, since nothing they point at is left. Returns `None` if
+/// `elf` isn't something we know how to take apart, such as one with more
+/// sections than fit in the header.
+pub fn strip(elf: &[u8]) -> Option<Vec<u8>> {
+    let header = Header::parse(elf)?;
+    let endian = header.endian;
+    let (ehsize, shentsize) = if header.class64 { (64, 64) } else { (52, 40) };
+    if header.ehsize < ehsize || header.shentsize < shentsize {
+        return None;
+    }
+    if header.shnum == 0 || header.shstrndx == 0 || header.shstrndx >= header.shnum {
+        return None;
+    }
+    let names = header.section(elf, header.shstrndx)?.data(elf)?;
+    let name = |off: u32| {
+        let name = names.get(off as usize..)?;
+        Some(&name[..name.iter().position(|c| *c == 0)?])
+    };
+
+    let word = if header.class64 { 8 } else { 4 };
+    let (phoff, phnum, shoff, offset_field) = if header.class64 {
+        (0x20, 0x38, 0x28, 24)
+    } else {
+        (0x1C, 0x2C, 0x20, 16)
+    };
+
+    let mut out = elf.get(..header.ehsize)?.to_vec();
+    endian.put(&mut out, phoff, 0, word);
+    endian.put(&mut out, phnum, 0, 2);
+
+    let mut headers = Vec::with_capacity(header.shnum * header.shentsize);
+    for idx in 0..header.shnum {
+        let off = header.section_offset(idx)?;
+        let mut raw = elf.get(off..off.checked_add(header.shentsize)?)?.to_vec();
+        let section = header.section(elf, idx)?;
+
+        if idx != 0 && section.kind != SHT_NOBITS {
+            if keep(section.kind, name(section.name)?) {
+                out.resize(out.len().next_multiple_of(section.align.max(1)), 0);
+                endian.put(&mut raw, offset_field, out.len() as u64, word);
+                out.extend_from_slice(section.data(elf)?);
+            } else {
+                endian.put(&mut raw, 4, SHT_NOBITS as u64, 4);
+                endian.put(&mut raw, offset_field, out.len() as u64, word);
+            }
+        }
+        headers.extend_from_slice(&raw);
+    }
+
+    out.resize(out.len().next_multiple_of(word), 0);
+    let len = out.len() as u64;
+    endian.put(&mut out, shoff, len, word);
+    out.extend_from_slice(&headers);
+    Some(out)
+}
+
+/// Walk the notes in a `SHT_NOTE` section looking for `NT_GNU_BUILD_ID`.
+/// The name and descriptor each start on an `align` boundary from the top
+/// of the section, so with 8-byte alignment the descriptor of a `GNU\0`
+/// note sits right after the name, with no padding between.
+pub fn gnu_build_id(notes: &[u8], endian: Endian, align: usize) -> Option<&[u8]> {
+    let pad = |n: usize| n.checked_add(align - 1).map(|n| n & !(align - 1));
+
+    let mut off = 0;
+    while off + 12 <= notes.len() {
+        let namesz = endian.u32(notes, off)? as usize;
+        let descsz = endian.u32(notes, off + 4)? as usize;
+        let kind = endian.u32(notes, off + 8)?;
+
+        let name_start = off + 12;
+        let desc_start = pad(name_start.checked_add(namesz)?)?;
+        let desc_end = desc_start.checked_add(descsz)?;
+
+        let name = notes.get(name_start..name_start.checked_add(namesz)?)?;
+        let desc = notes.get(desc_start..desc_end)?;
+
+        if kind == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc);
+        }
+
+        off = pad(desc_end)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{build_id, build_id_at, gnu_build_id, strip, Endian, Header, SHT_NOBITS};
+    use crate::testing;
+
+    const ID: [u8; 20] = [
+        0x1c, 0x54, 0xe0, 0x4f, 0xcf, 0x76, 0x0c, 0x42, 0x8d, 0x0a, 0xfa, 0x79, 0xa3, 0x3f, 0xfb,
+        0x8e, 0x06, 0x8d, 0x35, 0xd5,
+    ];
+
+    fn put(buf: &mut Vec<u8>, endian: Endian, v: u64, width: usize) {
+        let bytes = match endian {
+            Endian::Little => v.to_le_bytes(),
+            Endian::Big => v.to_be_bytes(),
+        };
+        match endian {
+            Endian::Little => buf.extend_from_slice(&bytes[..width]),
+            Endian::Big => buf.extend_from_slice(&bytes[8 - width..]),
+        }
+    }
+
+    fn pad(buf: &mut Vec<u8>, align: usize) {
+        while buf.len() % align != 0 {
+            buf.push(0);
+        }
+    }
+
+    fn note(buf: &mut Vec<u8>, endian: Endian, align: usize, name: &[u8], kind: u32, desc: &[u8]) {
+        put(buf, endian, name.len() as u64, 4);
+        put(buf, endian, desc.len() as u64, 4);
+        put(buf, endian, kind as u64, 4);
+        buf.extend_from_slice(name);
+        pad(buf, align);
+        buf.extend_from_slice(desc);
+        pad(buf, align);
+    }
+
+    fn notes(endian: Endian, align: usize) -> Vec<u8> {
+        let mut buf = vec![];
+        note(&mut buf, endian, align, b"GNU\0", 1, &[0; 16]);
+        note(&mut buf, endian, align, b"GNU\0", 3, &ID);
+        buf
+    }
+
+    /// Build an ELF with a null section and a single note section.
+    fn elf(class64: bool, endian: Endian, align: usize) -> Vec<u8> {
+        let notes = notes(endian, align);
+        let (ehsize, shentsize) = if class64 { (64, 64) } else { (52, 40) };
+        let word = if class64 { 8 } else { 4 };
+
+        let mut buf = b"\x7fELF".to_vec();
+        buf.push(if class64 { 2 } else { 1 });
+        buf.push(match endian {
+            Endian::Little => 1,
+            Endian::Big => 2,
+        });
+        buf.push(1);
+        buf.resize(16, 0);
+        put(&mut buf, endian, 1, 2); // e_type
+        put(&mut buf, endian, 0, 2); // e_machine
+        put(&mut buf, endian, 1, 4); // e_version
+        put(&mut buf, endian, 0, word); // e_entry
+        put(&mut buf, endian, 0, word); // e_phoff
+        let shoff = ehsize + notes.len();
+        put(&mut buf, endian, shoff as u64, word); // e_shoff
+        put(&mut buf, endian, 0, 4); // e_flags
+        put(&mut buf, endian, ehsize as u64, 2); // e_ehsize
+        put(&mut buf, endian, 0, 2); // e_phentsize
+        put(&mut buf, endian, 0, 2); // e_phnum
+        put(&mut buf, endian, shentsize as u64, 2); // e_shentsize
+        put(&mut buf, endian, 2, 2); // e_shnum
+        put(&mut buf, endian, 0, 2); // e_shstrndx
+        assert_eq!(ehsize, buf.len());
+
+        buf.extend_from_slice(&notes);
+        buf.resize(shoff + shentsize, 0);
+
+        put(&mut buf, endian, 0, 4); // sh_name
+        put(&mut buf, endian, 7, 4); // sh_type
+        put(&mut buf, endian, 2, word); // sh_flags
+        put(&mut buf, endian, 0, word); // sh_addr
+        put(&mut buf, endian, ehsize as u64, word); // sh_offset
+        put(&mut buf, endian, notes.len() as u64, word); // sh_size
+        put(&mut buf, endian, 0, 4); // sh_link
+        put(&mut buf, endian, 0, 4); // sh_info
+        put(&mut buf, endian, align as u64, word); // sh_addralign
+        put(&mut buf, endian, 0, word); // sh_entsize
+        assert_eq!(shoff + shentsize * 2, buf.len());
+        buf
+    }
+
+    #[test]
+    fn notes_little_endian() {
+        for align in [4, 8] {
+            let notes = notes(Endian::Little, align);
+            assert_eq!(Some(&ID[..]), gnu_build_id(&notes, Endian::Little, align));
+        }
+    }
+
+    #[test]
+    fn notes_big_endian() {
+        for align in [4, 8] {
+            let notes = notes(Endian::Big, align);
+            assert_eq!(Some(&ID[..]), gnu_build_id(&notes, Endian::Big, align));
+        }
+    }
+
+    #[test]
+    fn elf_build_id() {
+        for class64 in [false, true] {
+            for endian in [Endian::Little, Endian::Big] {
+                for align in [4, 8] {
+                    let elf = elf(class64, endian, align);
+                    assert_eq!(Some(&ID[..]), build_id(&elf));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strip_sections() {
+        let notes = notes(Endian::Little, 4);
+        let text = vec![0xccu8; 4096];
+        let full = testing::elf(&[
+            (".note.gnu.build-id", 7, &notes[..]),
+            (".text", 1, &text[..]),
+            (".debug_info", 1, &b"pretend this is DWARF"[..]),
+            (".bss", 8, &[][..]),
+        ]);
+        let stripped = strip(&full).unwrap();
+        assert!(stripped.len() < full.len() - 4000);
+        assert_eq!(Some(&ID[..]), build_id(&stripped));
+
+        let header = Header::parse(&stripped).unwrap();
+        assert_eq!(6, header.shnum);
+        let text = header.section(&stripped, 2).unwrap();
+        assert_eq!((SHT_NOBITS, 4096), (text.kind, text.size));
+        let info = header.section(&stripped, 3).unwrap();
+        assert_eq!(1, info.kind);
+        assert_eq!(b"pretend this is DWARF", info.data(&stripped).unwrap());
+        let names = header.section(&stripped, 5).unwrap();
+        assert!(names
+            .data(&stripped)
+            .unwrap()
+            .windows(12)
+            .any(|v| v == b".debug_info\0"));
+
+        assert_eq!(None, strip(b"!<arch>\n"));
+        assert_eq!(None, strip(&elf(true, Endian::Little, 4)));
+    }
+
+    #[test]
+    fn file_build_id() {
+        let path = std::env::temp_dir().join(format!("debugfs-{}-elf", std::process::id()));
+        for class64 in [false, true] {
+            for endian in [Endian::Little, Endian::Big] {
+                std::fs::write(&path, elf(class64, endian, 4)).unwrap();
+                let file = std::fs::File::open(&path).unwrap();
+                assert_eq!(Some(ID.to_vec()), build_id_at(&file));
+            }
+        }
+        std::fs::write(&path, b"!<arch>\n").unwrap();
+        assert_eq!(None, build_id_at(&std::fs::File::open(&path).unwrap()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn not_elf() {
+        assert_eq!(None, build_id(b"!<arch>\n"));
+        assert_eq!(None, build_id(&elf(true, Endian::Little, 4)[..80]));
+    }
+}
+
+// vim: foldmethod=marker
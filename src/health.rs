@@ -0,0 +1,154 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use super::{redact, source, Options};
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+    Request, Response,
+};
+use hyper_util::rt::TokioIo;
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+
+/// Longest we'll wait on the mirror before calling it down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check that each of `urls` is there and isn't empty, asking the mirror
+/// with `options`, and saying what's wrong with the first one that isn't.
+pub async fn check(urls: &[String], options: &Options) -> Result<(), String> {
+    let checks = urls.iter().map(|url| async move {
+        let file = tokio::time::timeout(CHECK_TIMEOUT, source::open(url, options)).await;
+        match file {
+            Err(_) => Err(format!("{}: timed out", redact(url))),
+            Ok(Err(err)) => Err(format!("{}: {}", redact(url), err)),
+            Ok(Ok(file)) if file.content_length() == 0 => Err(format!("{}: empty", redact(url))),
+            Ok(Ok(_)) => Ok(()),
+        }
+    });
+    futures::future::join_all(checks)
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// Answer `GET /healthz` on `listener` with a 200 if [check] passes for
+/// `urls`, or a 503 saying why not. Anything else is a 404.
+pub async fn serve(listener: TcpListener, urls: Arc<Vec<String>>, options: Arc<Options>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let (urls, options) = (urls.clone(), options.clone());
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let (urls, options) = (urls.clone(), options.clone());
+                async move { Ok::<_, Infallible>(respond(req, &urls, &options).await) }
+            });
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("health check client went away: {:?}", err);
+            }
+        });
+    }
+}
+
+///
+async fn respond(
+    req: Request<Incoming>,
+    urls: &[String],
+    options: &Options,
+) -> Response<Full<Bytes>> {
+    let (status, body) = match req.uri().path() {
+        "/healthz" => match check(urls, options).await {
+            Ok(()) => (200, "ok\n".to_owned()),
+            Err(err) => {
+                tracing::warn!("health check failed: {}", err);
+                (503, format!("{err}\n"))
+            }
+        },
+        _ => (404, String::new()),
+    };
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, serve, Options};
+    use crate::testing::Mirror;
+    use std::sync::Arc;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[tokio::test]
+    async fn healthz() {
+        let addr = Mirror::new(&[
+            ("/amd64/Packages.xz", b"not really xz".to_vec()),
+            ("/arm64/Packages.xz", vec![]),
+        ])
+        .listen_tcp()
+        .await;
+        let url = |path: &str| format!("http://{addr}{path}");
+
+        let options = Options::default();
+        assert!(check(&[url("/amd64/Packages.xz")], &options).await.is_ok());
+        let err = check(
+            &[url("/amd64/Packages.xz"), url("/arm64/Packages.xz")],
+            &options,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.contains("empty"), "{err}");
+        assert!(check(&[url("/riscv64/Packages.xz")], &options)
+            .await
+            .is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let health = listener.local_addr().unwrap();
+        let urls = Arc::new(vec![url("/amd64/Packages.xz")]);
+        tokio::spawn(serve(listener, urls, Arc::new(options)));
+
+        for (path, status) in [("/healthz", "200"), ("/metrics", "404")] {
+            let mut stream = TcpStream::connect(health).await.unwrap();
+            stream
+                .write_all(
+                    format!("GET {path} HTTP/1.1\r\nhost: debugfs\r\nconnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(
+                response.starts_with(&format!("HTTP/1.1 {status} ")),
+                "{path}: {response}"
+            );
+        }
+    }
+}
+
+// vim: foldmethod=marker
@@ -0,0 +1,136 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use anyhow::Result;
+use sequoia_openpgp::{
+    cert::{Cert, CertParser},
+    parse::{
+        stream::{MessageLayer, MessageStructure, VerificationHelper, VerifierBuilder},
+        Parse,
+    },
+    policy::StandardPolicy,
+    KeyHandle,
+};
+use sha2::{Digest, Sha256};
+use std::{io::Read, path::Path};
+
+/// Hands every cert in the keyring to the verifier, and only accepts a
+/// message with at least one good signature.
+struct Helper {
+    certs: Vec<Cert>,
+}
+
+impl VerificationHelper for Helper {
+    fn get_certs(&mut self, _: &[KeyHandle]) -> sequoia_openpgp::Result<Vec<Cert>> {
+        Ok(self.certs.clone())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> sequoia_openpgp::Result<()> {
+        for layer in structure.into_iter() {
+            if let MessageLayer::SignatureGroup { results } = layer {
+                if results.iter().any(|result| result.is_ok()) {
+                    return Ok(());
+                }
+            }
+        }
+        Err(anyhow::anyhow!("no good signature from the keyring"))
+    }
+}
+
+/// Check the signature on an `InRelease` file against the certs in
+/// `keyring`, returning the signed `Release` text if it's good.
+pub fn verify(keyring: &Path, signed: &[u8]) -> Result<Vec<u8>> {
+    let certs = CertParser::from_file(keyring)?.collect::<Result<Vec<_>>>()?;
+    let policy = StandardPolicy::new();
+
+    let mut verifier =
+        VerifierBuilder::from_bytes(signed)?.with_policy(&policy, None, Helper { certs })?;
+    let mut release = vec![];
+    verifier.read_to_end(&mut release)?;
+    Ok(release)
+}
+
+/// Hex encoded SHA256 of `body`, as it's written in `Release` and
+/// `Packages` files.
+pub fn sha256(body: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(body))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sha256, verify};
+    use sequoia_openpgp::{
+        cert::CertBuilder,
+        policy::StandardPolicy,
+        serialize::{
+            stream::{Message, Signer},
+            Serialize,
+        },
+    };
+    use std::io::Write;
+
+    #[test]
+    fn sha256_hex() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            sha256(b"")
+        );
+    }
+
+    #[test]
+    fn inrelease() {
+        let (cert, _) = CertBuilder::general_purpose(None, Some("archive signing key"))
+            .generate()
+            .unwrap();
+        let keyring = std::env::temp_dir().join(format!("debugfs-{}.pgp", std::process::id()));
+        cert.serialize(&mut std::fs::File::create(&keyring).unwrap())
+            .unwrap();
+
+        let policy = StandardPolicy::new();
+        let keypair = cert
+            .keys()
+            .with_policy(&policy, None)
+            .secret()
+            .for_signing()
+            .next()
+            .unwrap()
+            .key()
+            .clone()
+            .into_keypair()
+            .unwrap();
+
+        let release = b"Suite: unstable-debug\nComponents: main\n";
+        let mut signed = vec![];
+        let message = Message::new(&mut signed);
+        let mut message = Signer::new(message, keypair).cleartext().build().unwrap();
+        message.write_all(release).unwrap();
+        message.finalize().unwrap();
+
+        assert_eq!(&release[..], &verify(&keyring, &signed).unwrap()[..]);
+
+        let tampered = String::from_utf8(signed)
+            .unwrap()
+            .replace("unstable-debug", "bookworm-debug");
+        assert!(verify(&keyring, tampered.as_bytes()).is_err());
+        assert!(verify(&keyring, release).is_err());
+    }
+}
+
+// vim: foldmethod=marker
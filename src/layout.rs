@@ -0,0 +1,117 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use std::sync::Arc;
+
+/// Where things are in an apt archive: the indices for each component and
+/// architecture of a suite, and each debug file inside its package. Paths
+/// are relative to the archive root, or to the suite's directory for
+/// [Layout::index_dir], since that's how its `Release` file lists them.
+pub trait Layout: Send + Sync + std::fmt::Debug {
+    /// Directory holding `suite`'s `Release` and `InRelease` files.
+    fn suite_dir(&self, suite: &str) -> String;
+
+    /// Directory holding the `Packages` files (and `by-hash/`) for
+    /// `component` and `arch`, under [Layout::suite_dir].
+    fn index_dir(&self, component: &str, arch: &str) -> String;
+
+    /// Path of the debug file for `build_id` in the `data.tar` of its
+    /// package, as the tar has it.
+    fn debug_path(&self, build_id: &str) -> String;
+}
+
+/// The layout of a Debian archive, such as `deb.debian.org/debian-debug`:
+/// `dists/<suite>/<component>/binary-<arch>/Packages.xz`, with debug files
+/// under `/usr/lib/debug/.build-id/`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Debian;
+
+impl Layout for Debian {
+    fn suite_dir(&self, suite: &str) -> String {
+        format!("dists/{suite}")
+    }
+
+    fn index_dir(&self, component: &str, arch: &str) -> String {
+        format!("{component}/binary-{arch}")
+    }
+
+    fn debug_path(&self, build_id: &str) -> String {
+        let (prefix, rest) = build_id.split_at(2.min(build_id.len()));
+        format!("./usr/lib/debug/.build-id/{prefix}/{rest}.debug")
+    }
+}
+
+/// Ubuntu's debug packages (`.ddeb` files) as Launchpad publishes them
+/// next to the regular ones, in PPAs and the primary archive:
+/// `dists/<suite>/<component>/debug/binary-<arch>/`. `ddebs.ubuntu.com`
+/// itself is laid out like [Debian].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ddebs;
+
+impl Layout for Ddebs {
+    fn suite_dir(&self, suite: &str) -> String {
+        Debian.suite_dir(suite)
+    }
+
+    fn index_dir(&self, component: &str, arch: &str) -> String {
+        format!("{component}/debug/binary-{arch}")
+    }
+
+    fn debug_path(&self, build_id: &str) -> String {
+        Debian.debug_path(build_id)
+    }
+}
+
+/// The layout called `name` on the command line.
+pub fn parse(name: &str) -> Result<Arc<dyn Layout>, String> {
+    match name {
+        "debian" => Ok(Arc::new(Debian)),
+        "ddebs" => Ok(Arc::new(Ddebs)),
+        _ => Err(format!("expected debian or ddebs, got {name:?}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Ddebs, Debian, Layout};
+
+    #[test]
+    fn debian() {
+        assert_eq!("dists/sid-debug", Debian.suite_dir("sid-debug"));
+        assert_eq!("main/binary-arm64", Debian.index_dir("main", "arm64"));
+        assert_eq!(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            Debian.debug_path("1c54e04fcf760c428d0afa79a33ffb8e068d35d5")
+        );
+    }
+
+    #[test]
+    fn ddebs() {
+        assert_eq!("dists/noble", Ddebs.suite_dir("noble"));
+        assert_eq!("main/debug/binary-amd64", Ddebs.index_dir("main", "amd64"));
+        assert_eq!(
+            "main/debug/binary-amd64",
+            parse("ddebs").unwrap().index_dir("main", "amd64")
+        );
+        assert!(parse("ubuntu").is_err());
+    }
+}
+
+// vim: foldmethod=marker
@@ -20,71 +20,579 @@
 
 #![feature(trait_alias)]
 
-use arigato::server::AsyncServer;
-use std::str::FromStr;
-use tokio::io::AsyncReadExt;
+use arigato::server::{AsyncServer, FileError, OpenFile as OpenFileTrait};
+use clap::Parser;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio_stream::StreamExt;
 use tokio_tar::Archive;
 use tracing_subscriber::{fmt::format::FmtSpan, FmtSubscriber};
 use xz2::stream::Action;
 
 mod ar;
+mod cache;
 mod deb822;
 mod debugfs;
+mod debuginfod;
+mod elf;
+mod health;
 mod hrange;
+mod keyring;
+mod layout;
+mod metrics;
+mod source;
+#[cfg(test)]
+mod testing;
 
-use ar::{Deb, Decompress};
+use ar::{
+    data_tar, Buffers, Deb, Decompress, Mismatch, NotADeb, Truncated, TruncatedStream, Unsupported,
+};
+use cache::{CachedFile, DiskCache};
 use debugfs::Debug;
-use hrange::HttpFile;
+use hrange::{redact, HttpFile, Options, Timeout};
+use layout::{Debian, Layout};
+use metrics::METRICS;
 use xz2::{read::XzDecoder, stream::Status};
 
+/// Serve a `/usr/lib/debug/.build-id/` tree out of an apt archive of debug
+/// packages over 9p.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Do something other than serve.
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Address to listen for 9p connections on.
+    #[arg(long, default_value = "0.0.0.0:5641", conflicts_with = "unix")]
+    listen: String,
+
+    /// Listen on a Unix socket at this path instead of a TCP port. A
+    /// stale socket left behind by a previous run is removed first.
+    #[arg(long)]
+    unix: Option<PathBuf>,
+
+    /// Root of the apt archive, the directory holding `dists/` and `pool/`.
+    #[arg(long, default_value = "http://archive.adref/debian-debug/")]
+    archive_root: String,
+
+    /// Fetch `.deb` files from under this URL rather than --archive-root,
+    /// for mirrors that keep `pool/` on another host than `dists/`.
+    #[arg(long)]
+    pool_root: Option<String>,
+
+    /// How the archive is laid out: `debian`, or `ddebs` for the `.ddeb`
+    /// files Launchpad publishes under `<component>/debug/` in Ubuntu's
+    /// archive and in PPAs. `ddebs.ubuntu.com` is laid out like `debian`.
+    #[arg(long, default_value = "debian", value_parser = layout::parse)]
+    layout: std::sync::Arc<dyn Layout>,
+
+    /// Suite to serve, such as `unstable-debug`.
+    #[arg(long, default_value = "unstable-debug")]
+    suite: String,
+
+    /// Component of the suite to serve.
+    #[arg(long, default_value = "main")]
+    component: String,
+
+    /// Serve an extra filesystem, as `NAME=ARCHIVE_ROOT,SUITE,COMPONENT,ARCH`.
+    /// Can be given more than once; if it is, only these are served and
+    /// --archive-root, --pool-root, --suite and --component are ignored.
+    #[arg(long = "mount", value_name = "NAME=ARCHIVE_ROOT,SUITE,COMPONENT,ARCH")]
+    mounts: Vec<Mount>,
+
+    /// Serve Prometheus metrics over HTTP on this address.
+    #[arg(long)]
+    metrics_listen: Option<String>,
+
+    /// Answer `/healthz` over HTTP on this address, with a 200 once every
+    /// served `Packages.xz` is reachable on the mirror and a 503 otherwise.
+    #[arg(long)]
+    health_listen: Option<String>,
+
+    /// Also answer debuginfod's `GET /buildid/<build-id>/debuginfo` over
+    /// HTTP on this address, out of the same filesystems, so that
+    /// `DEBUGINFOD_URLS` can point here.
+    #[arg(long)]
+    debuginfod_listen: Option<String>,
+
+    /// Size in bytes of the pipe between the xz decoder and the client
+    /// reading each debug file. Defaults to 32 KiB.
+    #[arg(long, value_name = "BYTES")]
+    decompress_pipe: Option<usize>,
+
+    /// Compressed bytes to read out of a package at a time. Defaults to
+    /// 32 KiB.
+    #[arg(long, value_name = "BYTES")]
+    decompress_input: Option<usize>,
+
+    /// Decompressed bytes to hand over to the pipe at a time. Defaults to
+    /// 128 KiB.
+    #[arg(long, value_name = "BYTES")]
+    decompress_output: Option<usize>,
+
+    /// Keep extracted debug files in this directory, and serve them from
+    /// there from then on.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Most bytes to keep in --cache-dir before evicting the least
+    /// recently opened debug files.
+    #[arg(long, value_name = "BYTES", default_value_t = 10 << 30)]
+    cache_size: u64,
+
+    /// Keep debug files in --cache-dir zstd-compressed, decompressing them
+    /// as they're read. Saves a good deal of disk, at the cost of some CPU
+    /// on every read.
+    #[arg(long, requires = "cache_dir")]
+    cache_compress: bool,
+
+    /// Warm the cache at startup with the debug files for the packages and
+    /// build-ids listed in this file, one to a line. Blank lines and lines
+    /// starting with `#` are skipped. Serving starts right away; progress
+    /// is logged as it goes.
+    #[arg(long, value_name = "FILE", requires = "cache_dir")]
+    prefetch: Option<PathBuf>,
+
+    /// Serve only the sections of each debug file that a debugger reads,
+    /// such as the DWARF and the symbol table, rather than all of it. Each
+    /// debug file is read into memory whole to do this.
+    #[arg(long)]
+    strip: bool,
+
+    /// Most `.deb` files to fetch from the mirror at once, per filesystem;
+    /// opens past this wait their turn. 0 means no limit.
+    #[arg(long, default_value_t = 8)]
+    max_fetches: usize,
+
+    /// Most bytes a `Packages` file can decompress to before loading the
+    /// index fails, rather than holding all of it in memory. 0 means no
+    /// limit.
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 30)]
+    max_index_size: u64,
+
+    /// Fail attaches when the index doesn't list a single build-id, as
+    /// happens with the wrong suite or component, rather than serving an
+    /// empty tree.
+    #[arg(long)]
+    fail_on_empty_index: bool,
+
+    /// Seconds to wait on dialing the mirror, including the TLS and HTTP
+    /// handshakes, before giving up.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    connect_timeout: u64,
+
+    /// Seconds to wait on the mirror to answer a request, or to send more
+    /// of a response, before giving up.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    read_timeout: u64,
+
+    /// Most verbose level to log at: error, warn, info, debug or trace.
+    #[arg(long, env = "DEBUGFS_LOG", default_value = "info")]
+    log_level: tracing::Level,
+}
+
+///
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Write the debug file for a build-id to stdout, out of the first
+    /// filesystem that has it, without serving anything. Failures are
+    /// reported by the errno a 9p client would have seen.
+    Fetch {
+        /// Build-id to fetch, in hex.
+        build_id: String,
+
+        /// Write the debug file here rather than to stdout.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
+
+impl Args {
+    /// Decompress buffer sizes, with any we weren't given left at the
+    /// defaults.
+    fn buffers(&self) -> Buffers {
+        let mut buffers = Buffers::default();
+        if let Some(pipe) = self.decompress_pipe {
+            buffers = buffers.with_pipe(pipe);
+        }
+        if let Some(input) = self.decompress_input {
+            buffers = buffers.with_input(input);
+        }
+        if let Some(output) = self.decompress_output {
+            buffers = buffers.with_output(output);
+        }
+        buffers
+    }
+
+    /// How to talk to the mirror.
+    fn http_options(&self) -> Options {
+        Options::default()
+            .with_connect_timeout(Duration::from_secs(self.connect_timeout))
+            .with_read_timeout(Duration::from_secs(self.read_timeout))
+    }
+}
+
+/// A filesystem to serve under the attach name `name`.
+#[derive(Debug, Clone, PartialEq)]
+struct Mount {
+    name: String,
+    archive_root: String,
+    suite: String,
+    component: String,
+    arch: String,
+}
+
+impl std::str::FromStr for Mount {
+    type Err = String;
+
+    fn from_str(v: &str) -> Result<Self, String> {
+        let (name, rest) = v
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=ARCHIVE_ROOT,SUITE,COMPONENT,ARCH, got {v:?}"))?;
+        match rest.split(',').collect::<Vec<_>>()[..] {
+            [archive_root, suite, component, arch]
+                if ![name, archive_root, suite, component, arch].contains(&"") =>
+            {
+                Ok(Mount {
+                    name: name.to_owned(),
+                    archive_root: archive_root.to_owned(),
+                    suite: suite.to_owned(),
+                    component: component.to_owned(),
+                    arch: arch.to_owned(),
+                })
+            }
+            _ => Err(format!(
+                "expected NAME=ARCHIVE_ROOT,SUITE,COMPONENT,ARCH, got {v:?}"
+            )),
+        }
+    }
+}
+
+/// Packages and build-ids to prefetch, out of the file at `path`.
+fn read_prefetch(path: &Path) -> anyhow::Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect())
+}
+
+/// Remove the socket at `path` if there is one, refusing to touch anything
+/// that isn't a socket.
+fn remove_stale_socket(path: &Path) -> anyhow::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        v => v?,
+    };
+    if !metadata.file_type().is_socket() {
+        anyhow::bail!("{} exists and isn't a socket", path.display());
+    }
+    tracing::info!("removing stale socket {}", path.display());
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Every filesystem `args` asks us to serve, by attach name, along with
+/// whether its `Packages.xz` should be health checked.
+fn filesystems(args: &Args) -> anyhow::Result<Vec<(String, Debug, bool)>> {
+    let buffers = args.buffers();
+    let http = args.http_options();
+    let cache = match &args.cache_dir {
+        None => None,
+        Some(dir) => Some(std::sync::Arc::new(
+            DiskCache::new(dir, args.cache_size)?.with_compression(args.cache_compress),
+        )),
+    };
+    let configure = |debug: Debug| {
+        let debug = debug
+            .with_buffers(buffers)
+            .with_max_fetches(args.max_fetches)
+            .with_max_index_size(args.max_index_size)
+            .with_strip(args.strip)
+            .with_fail_on_empty_index(args.fail_on_empty_index)
+            .with_layout(args.layout.clone())
+            .with_http_options(http.clone());
+        match &cache {
+            None => debug,
+            Some(cache) => debug.with_cache(cache.clone()),
+        }
+    };
+
+    let mut filesystems = vec![];
+    if args.mounts.is_empty() {
+        let configure = |debug: Debug| match &args.pool_root {
+            None => configure(debug),
+            Some(pool_root) => configure(debug.with_pool_root(pool_root)),
+        };
+        // one filesystem per arch, named like `unstable-amd64`.
+        let name = args.suite.trim_end_matches("-debug");
+        for arch in ["amd64", "arm64", "riscv64"] {
+            let debug = configure(Debug::new(
+                &args.archive_root,
+                &args.suite,
+                &args.component,
+                arch,
+            ));
+            filesystems.push((format!("{name}-{arch}"), debug, true));
+        }
+        let debug = configure(
+            Debug::new(&args.archive_root, &args.suite, &args.component, "amd64")
+                .with_suites(&["bookworm-debug"]),
+        );
+        filesystems.push(("amd64".to_owned(), debug, false));
+    }
+    for mount in &args.mounts {
+        tracing::info!("serving {:?}", mount);
+        let debug = configure(Debug::new(
+            &mount.archive_root,
+            &mount.suite,
+            &mount.component,
+            &mount.arch,
+        ));
+        filesystems.push((mount.name.clone(), debug, true));
+    }
+    Ok(filesystems)
+}
+
+/// Write the debug file for `build_id`, out of the first of `filesystems`
+/// to have it, to `output`, or to stdout without one.
+async fn fetch(filesystems: &[Debug], build_id: &str, output: Option<&Path>) -> anyhow::Result<()> {
+    let mut header = None;
+    let mut failed = None;
+    for debug in filesystems {
+        match debug.lookup(build_id).await {
+            Ok(None) => continue,
+            Ok(Some(v)) => {
+                header = Some(v);
+                break;
+            }
+            Err(FileError(_, name)) => {
+                tracing::warn!("couldn't look up {}: {}", build_id, name);
+                failed = Some(name);
+            }
+        }
+    }
+    let header = match (header, failed) {
+        (Some(v), _) => v,
+        (None, Some(name)) => anyhow::bail!("couldn't load the index: {}", name),
+        (None, None) => anyhow::bail!("{} isn't in the index: ENOENT", build_id),
+    };
+
+    let mut file = header
+        .open_read()
+        .await
+        .map_err(|FileError(_, name)| anyhow::anyhow!("couldn't open {}: {}", build_id, name))?;
+    let mut out: std::pin::Pin<Box<dyn tokio::io::AsyncWrite>> = match output {
+        None => Box::pin(tokio::io::stdout()),
+        Some(path) => Box::pin(tokio::fs::File::create(path).await?),
+    };
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut off = 0;
+    loop {
+        let n = file
+            .read_at(&mut buf, off)
+            .await
+            .map_err(|FileError(_, name)| anyhow::anyhow!("couldn't read {}: {}", build_id, name))?
+            as usize;
+        if n == 0 {
+            break;
+        }
+        out.write_all(&buf[..n]).await?;
+        off += n as u64;
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+/// Wait for a SIGINT or SIGTERM, returning which one it was.
+async fn shutdown() -> std::io::Result<&'static str> {
+    let mut term = signal(SignalKind::terminate())?;
+    tokio::select! {
+        v = tokio::signal::ctrl_c() => v.map(|_| "SIGINT"),
+        _ = term.recv() => Ok("SIGTERM"),
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let log_level = "info";
+    let args = Args::parse();
 
     let subscriber = FmtSubscriber::builder()
         .with_writer(std::io::stderr)
-        .with_max_level(tracing::Level::from_str(log_level).unwrap())
+        .with_max_level(args.log_level)
         .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
-    let srv = AsyncServer::builder()
-        .with_tcp_listen_address("0.0.0.0:5641")
-        .with_filesystem(
-            "unstable-amd64",
-            Debug::new(
-                "http://archive.adref/debian-debug/",
-                "unstable-debug",
-                "main",
-                "amd64",
-            ),
-        )
-        .with_filesystem(
-            "unstable-arm64",
-            Debug::new(
-                "http://archive.adref/debian-debug/",
-                "unstable-debug",
-                "main",
-                "arm64",
-            ),
-        )
-        .with_filesystem(
-            "unstable-riscv64",
-            Debug::new(
-                "http://archive.adref/debian-debug/",
-                "unstable-debug",
-                "main",
-                "riscv64",
+    let filesystems = filesystems(&args)?;
+    if let Some(Command::Fetch { build_id, output }) = &args.command {
+        let filesystems: Vec<_> = filesystems.into_iter().map(|(_, v, _)| v).collect();
+        return fetch(&filesystems, build_id, output.as_deref()).await;
+    }
+
+    if let Some(addr) = &args.metrics_listen {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!("serving metrics on {}", listener.local_addr()?);
+        tokio::spawn(metrics::serve(listener));
+    }
+
+    let mut builder = match &args.unix {
+        None => AsyncServer::builder().with_tcp_listen_address(&args.listen),
+        Some(path) => {
+            remove_stale_socket(path)?;
+            AsyncServer::builder().with_unix_listen_address(path)
+        }
+    };
+
+    // every filesystem numbers its qids on its own; that's fine, since
+    // each attach name is its own mount on the client.
+    let mut packages_urls = vec![];
+    let mut handles = vec![];
+    for (name, debug, health) in filesystems {
+        if health {
+            packages_urls.extend(debug.packages_urls());
+        }
+        handles.push(debug.handle());
+        builder = builder.with_filesystem(&name, debug);
+    }
+
+    if let Some(addr) = &args.health_listen {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!("serving health checks on {}", listener.local_addr()?);
+        packages_urls.dedup();
+        tokio::spawn(health::serve(
+            listener,
+            std::sync::Arc::new(packages_urls),
+            std::sync::Arc::new(args.http_options()),
+        ));
+    }
+    let handles = std::sync::Arc::new(handles);
+    if let Some(addr) = &args.debuginfod_listen {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!("serving debuginfod on {}", listener.local_addr()?);
+        tokio::spawn(debuginfod::serve(listener, handles.clone()));
+    }
+    if let Some(path) = &args.prefetch {
+        let wanted = read_prefetch(path)?;
+        tracing::info!("prefetching {} packages and build-ids", wanted.len());
+        // one filesystem after another, so that any they have in common
+        // is only fetched the once.
+        let handles = handles.clone();
+        tokio::spawn(async move {
+            for handle in handles.iter() {
+                if let Err(err) = handle.prefetch(&wanted).await {
+                    tracing::warn!("failed to prefetch: {:?}", err);
+                }
+            }
+            tracing::info!("done prefetching");
+        });
+    }
+    let srv = builder.build().await.unwrap();
+    tokio::select! {
+        res = srv.serve() => res.unwrap(),
+        signal = shutdown() => tracing::info!("got {}, shutting down", signal?),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fetch, Args, Command, Mount};
+    use crate::{debugfs::Debug, testing, testing::Mirror};
+    use clap::Parser;
+
+    #[test]
+    fn parse_mount() {
+        assert_eq!(
+            Mount {
+                name: "noble-amd64".to_owned(),
+                archive_root: "http://ddebs.ubuntu.com/".to_owned(),
+                suite: "noble".to_owned(),
+                component: "main".to_owned(),
+                arch: "amd64".to_owned(),
+            },
+            "noble-amd64=http://ddebs.ubuntu.com/,noble,main,amd64"
+                .parse()
+                .unwrap()
+        );
+
+        for bad in [
+            "noble-amd64",
+            "noble-amd64=http://ddebs.ubuntu.com/,noble,main",
+            "noble-amd64=http://ddebs.ubuntu.com/,noble,main,amd64,extra",
+            "=http://ddebs.ubuntu.com/,noble,main,amd64",
+            "noble-amd64=http://ddebs.ubuntu.com/,,main,amd64",
+        ] {
+            assert!(bad.parse::<Mount>().is_err(), "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_build_id() {
+        let args = Args::try_parse_from([
+            "debugfs",
+            "fetch",
+            "1c54e04fcf760c428d0afa79a33ffb8e068d35d5",
+            "-o",
+            "zzuf.debug",
+        ])
+        .unwrap();
+        let Some(Command::Fetch { build_id, output }) = args.command else {
+            panic!("not a fetch");
+        };
+        assert_eq!("1c54e04fcf760c428d0afa79a33ffb8e068d35d5", build_id);
+        assert_eq!(Some("zzuf.debug".into()), output);
+
+        let deb = testing::deb(&[(
+            "./usr/lib/debug/.build-id/1c/54e04fcf760c428d0afa79a33ffb8e068d35d5.debug",
+            &b"\x7fELF pretend this is a debug file"[..],
+        )])
+        .await;
+        let packages = "Package: zzuf-dbgsym
+Build-Ids: 1c54e04fcf760c428d0afa79a33ffb8e068d35d5
+Filename: pool/zzuf-dbgsym.deb
+";
+        let addr = Mirror::new(&[
+            (
+                "/dists/unstable-debug/main/binary-amd64/Packages.xz",
+                testing::xz(packages.as_bytes()),
             ),
+            ("/pool/zzuf-dbgsym.deb", deb),
+        ])
+        .listen_tcp()
+        .await;
+        let filesystems = [Debug::new(
+            &format!("http://{addr}"),
+            "unstable-debug",
+            "main",
+            "amd64",
+        )];
+
+        let output =
+            std::env::temp_dir().join(format!("debugfs-{}-fetch.debug", std::process::id()));
+        fetch(&filesystems, &build_id, Some(&output)).await.unwrap();
+        assert_eq!(
+            b"\x7fELF pretend this is a debug file".to_vec(),
+            std::fs::read(&output).unwrap()
+        );
+        std::fs::remove_file(&output).unwrap();
+
+        let err = fetch(
+            &filesystems,
+            "204d62991035324322317de6f71f494c06a10d37",
+            Some(&output),
         )
-        .build()
         .await
-        .unwrap();
-    srv.serve().await.unwrap();
-
-    Ok(())
+        .unwrap_err();
+        assert!(err.to_string().contains("ENOENT"), "{err}");
+    }
 }
 
 // vim: foldmethod=marker
@@ -0,0 +1,320 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use arigato::server::{FileError, FileResult};
+use http_body_util::Full;
+use hyper::{body::Bytes, server::conn::http1, service::service_fn, Response};
+use hyper_util::rt::TokioIo;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::net::TcpListener;
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Process wide counters, rendered in the Prometheus text format.
+pub static METRICS: Metrics = Metrics::new();
+
+///
+#[derive(Debug)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    ///
+    const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    ///
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    ///
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    ///
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Durations, counted into [BUCKETS].
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    ///
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    ///
+    pub fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, le) in self.buckets.iter().zip(BUCKETS) {
+            if secs <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    ///
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        for (bucket, le) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{le=\"{le}\"}} {}",
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {count}");
+    }
+}
+
+///
+#[derive(Debug)]
+pub struct Metrics {
+    pub attaches: Counter,
+    pub index_refresh: Histogram,
+    pub deb_fetches: Counter,
+    pub mirror_bytes: Counter,
+    pub extract: Histogram,
+    pub cache_hits: Counter,
+    pub cache_misses: Counter,
+    /// errors handed back over 9p, by errno name.
+    errors: Mutex<BTreeMap<String, u64>>,
+    /// when the index being served was loaded, by suite.
+    index_loaded: Mutex<BTreeMap<String, Instant>>,
+}
+
+impl Metrics {
+    ///
+    const fn new() -> Self {
+        Metrics {
+            attaches: Counter::new(),
+            index_refresh: Histogram::new(),
+            deb_fetches: Counter::new(),
+            mirror_bytes: Counter::new(),
+            extract: Histogram::new(),
+            cache_hits: Counter::new(),
+            cache_misses: Counter::new(),
+            errors: Mutex::new(BTreeMap::new()),
+            index_loaded: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Note that the index for `suite` was loaded at `loaded`, so that how
+    /// stale it is can be told from then on.
+    pub fn index_loaded(&self, suite: &str, loaded: Instant) {
+        self.index_loaded
+            .lock()
+            .unwrap()
+            .insert(suite.to_owned(), loaded);
+    }
+
+    /// Count the error in `res`, if there is one, and pass it along.
+    pub fn track<T>(&self, res: FileResult<T>) -> FileResult<T> {
+        if let Err(FileError(_, name)) = &res {
+            *self.errors.lock().unwrap().entry(name.clone()).or_default() += 1;
+        }
+        res
+    }
+
+    /// Everything, in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (&self.attaches, "debugfs_attaches_total", "9p attaches."),
+            (
+                &self.deb_fetches,
+                "debugfs_deb_fetches_total",
+                "Pool .deb files opened on the mirror.",
+            ),
+            (
+                &self.mirror_bytes,
+                "debugfs_mirror_bytes_total",
+                "Bytes read from the mirror.",
+            ),
+            (
+                &self.cache_hits,
+                "debugfs_cache_hits_total",
+                "Debug files served from the disk cache.",
+            ),
+            (
+                &self.cache_misses,
+                "debugfs_cache_misses_total",
+                "Debug files extracted into the disk cache.",
+            ),
+        ];
+        for (counter, name, help) in counters {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", counter.get());
+        }
+
+        self.index_refresh.render(
+            &mut out,
+            "debugfs_index_refresh_seconds",
+            "Time to fetch and index the Packages files.",
+        );
+        self.extract.render(
+            &mut out,
+            "debugfs_extract_seconds",
+            "Time to find a debug file in its .deb.",
+        );
+
+        let name = "debugfs_errors_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Errors returned over 9p.\n# TYPE {name} counter"
+        );
+        for (errno, count) in self.errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "{name}{{errno=\"{errno}\"}} {count}");
+        }
+
+        let name = "debugfs_index_staleness_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Age of the index being served.\n# TYPE {name} gauge"
+        );
+        for (suite, loaded) in self.index_loaded.lock().unwrap().iter() {
+            let age = loaded.elapsed().as_secs();
+            let _ = writeln!(out, "{name}{{suite=\"{suite}\"}} {age}");
+        }
+        out
+    }
+}
+
+/// Answer every HTTP request on `listener` with [METRICS]; any path will
+/// do.
+pub async fn serve(listener: TcpListener) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+            let service = service_fn(|_| async {
+                let response = Response::builder()
+                    .header("content-type", "text/plain; version=0.0.4")
+                    .body(Full::new(Bytes::from(METRICS.render())));
+                Ok::<_, Infallible>(response.unwrap())
+            });
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("metrics client went away: {:?}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, Metrics, BUCKETS};
+    use arigato::server::FileError;
+    use std::time::{Duration, Instant};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[tokio::test]
+    async fn scrape() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener));
+
+        // a request head that doesn't come in all at once.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n")
+            .await
+            .unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        stream
+            .write_all(b"host: debugfs\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("\ndebugfs_attaches_total "), "{response}");
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.attaches.inc();
+        metrics.mirror_bytes.add(1024);
+        metrics.extract.observe(Duration::from_millis(30));
+        let _ = metrics.track::<()>(Err(FileError(5, "EIO".to_owned())));
+        let _ = metrics.track::<()>(Err(FileError(5, "EIO".to_owned())));
+        let _ = metrics.track(Ok(()));
+        metrics.index_loaded("unstable-debug", Instant::now() - Duration::from_secs(2));
+
+        let out = metrics.render();
+        assert!(out.contains("\ndebugfs_attaches_total 1\n"), "{out}");
+        assert!(out.contains("\ndebugfs_mirror_bytes_total 1024\n"), "{out}");
+        assert!(
+            out.contains("\ndebugfs_errors_total{errno=\"EIO\"} 2\n"),
+            "{out}"
+        );
+        assert!(out.contains("\ndebugfs_extract_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(out.contains("\ndebugfs_extract_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(out.contains("\ndebugfs_extract_seconds_count 1\n"));
+        assert!(out.contains("\n# TYPE debugfs_index_staleness_seconds gauge\n"));
+        assert!(
+            out.contains("\ndebugfs_index_staleness_seconds{suite=\"unstable-debug\"} 2\n"),
+            "{out}"
+        );
+        assert_eq!(
+            BUCKETS.len() + 1,
+            out.matches("debugfs_index_refresh_seconds_bucket").count()
+        );
+    }
+}
+
+// vim: foldmethod=marker
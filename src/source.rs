@@ -0,0 +1,168 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use super::{HttpFile, Options};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+/// Reader over a range of a [ReadAt].
+pub type RangeReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Somewhere the bytes of a pool file can be read from, a range at a time.
+pub trait ReadAt: Send + Sync {
+    /// Length of the whole file.
+    fn content_length(&self) -> u64;
+
+    /// Read `len` bytes from `start`, or fewer if the file ends first.
+    /// `None` if `start` is at or past the end.
+    fn reader_at_to(&self, start: u64, len: u64) -> BoxFuture<'_, Result<Option<RangeReader>>>;
+}
+
+/// Open `url` with whichever [ReadAt] its scheme calls for: `file://` for
+/// [LocalFile], or [HttpFile] with `options` for everything else.
+pub async fn open(url: &str, options: &Options) -> Result<Arc<dyn ReadAt>> {
+    Ok(match local_path(url) {
+        Some(path) => Arc::new(LocalFile::open(path).await?),
+        None => Arc::new(HttpFile::connect_with(url, options.clone()).await?),
+    })
+}
+
+/// Path named by a `file://` URL.
+pub fn local_path(url: &str) -> Option<&Path> {
+    url.strip_prefix("file://").map(Path::new)
+}
+
+impl ReadAt for HttpFile {
+    fn content_length(&self) -> u64 {
+        HttpFile::content_length(self)
+    }
+
+    fn reader_at_to(&self, start: u64, len: u64) -> BoxFuture<'_, Result<Option<RangeReader>>> {
+        Box::pin(async move {
+            Ok(HttpFile::reader_at_to(self, start, len)
+                .await?
+                .map(|v| Box::pin(v) as RangeReader))
+        })
+    }
+}
+
+/// File on a local filesystem, such as a mirror mounted over NFS.
+#[derive(Debug, Clone)]
+pub struct LocalFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl LocalFile {
+    ///
+    pub async fn open(path: &Path) -> Result<Self> {
+        let len = tokio::fs::metadata(path).await?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            len,
+        })
+    }
+}
+
+impl ReadAt for LocalFile {
+    fn content_length(&self) -> u64 {
+        self.len
+    }
+
+    fn reader_at_to(&self, start: u64, len: u64) -> BoxFuture<'_, Result<Option<RangeReader>>> {
+        Box::pin(async move {
+            if start >= self.len {
+                return Ok(None);
+            }
+            // each reader gets its own handle, so that they each have their
+            // own offset.
+            let mut file = tokio::fs::File::open(&self.path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            Ok(Some(Box::pin(file.take(len)) as RangeReader))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{local_path, open, Options};
+    use crate::{testing, Deb};
+    use std::path::Path;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn file_url() {
+        assert_eq!(
+            Some(Path::new("/srv/mirror/pool/main/a.deb")),
+            local_path("file:///srv/mirror/pool/main/a.deb")
+        );
+        assert_eq!(None, local_path("http://deb.debian.org/debian-debug/"));
+    }
+
+    #[tokio::test]
+    async fn local_file() {
+        let body: Vec<u8> = (0..=255).collect();
+        let path = std::env::temp_dir().join(format!("debugfs-{}-local", std::process::id()));
+        tokio::fs::write(&path, &body).await.unwrap();
+
+        let file = open(&format!("file://{}", path.display()), &Options::default())
+            .await
+            .unwrap();
+        assert_eq!(256, file.content_length());
+        for (start, len) in [(0, 60), (8, 60), (200, 56), (250, 60), (10, 0)] {
+            let mut buf = vec![];
+            let mut reader = file.reader_at_to(start, len).await.unwrap().unwrap();
+            reader.read_to_end(&mut buf).await.unwrap();
+            let end = ((start + len) as usize).min(body.len());
+            assert_eq!(&body[start as usize..end], &buf[..]);
+        }
+        assert!(file.reader_at_to(256, 1).await.unwrap().is_none());
+
+        tokio::fs::write(&path, testing::deb(&[("./a", b"hello")]).await)
+            .await
+            .unwrap();
+        let mut deb = Deb::open(&format!("file://{}", path.display()), &Options::default())
+            .await
+            .unwrap()
+            .check_format()
+            .await
+            .unwrap();
+        assert_eq!(
+            "control.tar.xz",
+            deb.next().await.unwrap().unwrap().header().identifier
+        );
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(
+            open("file:///nonexistent/debugfs/a.deb", &Options::default())
+                .await
+                .is_err()
+        );
+    }
+}
+
+// vim: foldmethod=marker
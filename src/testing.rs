@@ -0,0 +1,453 @@
+// {{{ Copyright (c) Paul R. Tagliamonte <paultag@gmail.com>, 2023-2024
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE. }}}
+
+use async_compression::tokio::write::{BzEncoder, GzipEncoder, LzmaEncoder, ZstdEncoder};
+use http_body_util::Full;
+use hyper::{
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response,
+};
+use hyper_util::rt::TokioIo;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::Write,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
+};
+use tokio_tar::{Builder, EntryType, Header};
+
+/// Timestamp on every member of an ar archive from [ar].
+pub const MTIME: u64 = 1_700_000_000;
+
+/// Build an ar archive out of the provided members.
+pub fn ar(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut buf = b"!<arch>\n".to_vec();
+    for (name, body) in members {
+        // GNU ar leaves everything but the size blank on the long name
+        // table.
+        let header = match *name {
+            "//" => format!("{name:<16}{:<32}{:<10}`\n", "", body.len()),
+            _ => format!(
+                "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                MTIME,
+                0,
+                0,
+                100644,
+                body.len()
+            ),
+        };
+        buf.extend_from_slice(header.as_bytes());
+        buf.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            buf.push(b'\n');
+        }
+    }
+    buf
+}
+
+/// Build a tar archive out of the provided files.
+pub async fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for (path, body) in files {
+        let mut header = Header::new_old();
+        // set the name by hand, since `set_path` strips a leading `./`.
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_size(body.len() as u64);
+        header.set_mode(0o644);
+        header.set_entry_type(EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, *body).await.unwrap();
+    }
+    builder.into_inner().await.unwrap()
+}
+
+///
+pub fn xz(data: &[u8]) -> Vec<u8> {
+    let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+///
+pub async fn zstd(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZstdEncoder::new(Vec::new());
+    encoder.write_all(data).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    encoder.into_inner()
+}
+
+///
+pub async fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzipEncoder::new(Vec::new());
+    encoder.write_all(data).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    encoder.into_inner()
+}
+
+///
+pub async fn bzip2(data: &[u8]) -> Vec<u8> {
+    let mut encoder = BzEncoder::new(Vec::new());
+    encoder.write_all(data).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    encoder.into_inner()
+}
+
+/// Legacy `.lzma`, from before xz.
+pub async fn lzma(data: &[u8]) -> Vec<u8> {
+    let mut encoder = LzmaEncoder::new(Vec::new());
+    encoder.write_all(data).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    encoder.into_inner()
+}
+
+/// Build a little endian ELF64 with a null section followed by
+/// `sections`, as (name, type, contents), and then a `.shstrtab`.
+pub fn elf(sections: &[(&str, u32, &[u8])]) -> Vec<u8> {
+    let mut names = vec![0u8];
+    let mut name_offsets = vec![];
+    for (name, _, _) in sections.iter().chain([(".shstrtab", 3, &[][..])].iter()) {
+        name_offsets.push(names.len());
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+
+    let mut body = vec![];
+    let mut headers = vec![0u8; 64];
+    let contents = sections
+        .iter()
+        .map(|(_, kind, data)| (*kind, *data))
+        .chain([(3, &names[..])]);
+    for ((kind, data), name) in contents.zip(name_offsets) {
+        let offset = 64 + body.len();
+        body.extend_from_slice(data);
+        body.resize(body.len().next_multiple_of(8), 0);
+        headers.extend_from_slice(&(name as u32).to_le_bytes()); // sh_name
+        headers.extend_from_slice(&kind.to_le_bytes()); // sh_type
+        headers.extend_from_slice(&[0; 16]); // sh_flags, sh_addr
+        headers.extend_from_slice(&(offset as u64).to_le_bytes()); // sh_offset
+        headers.extend_from_slice(&(data.len() as u64).to_le_bytes()); // sh_size
+        headers.extend_from_slice(&[0; 8]); // sh_link, sh_info
+        headers.extend_from_slice(&4u64.to_le_bytes()); // sh_addralign
+        headers.extend_from_slice(&[0; 8]); // sh_entsize
+    }
+    let shnum = (headers.len() / 64) as u16;
+
+    let mut buf = b"\x7fELF\x02\x01\x01".to_vec();
+    buf.resize(16, 0);
+    buf.extend_from_slice(&1u16.to_le_bytes()); // e_type
+    buf.extend_from_slice(&0u16.to_le_bytes()); // e_machine
+    buf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    buf.extend_from_slice(&[0; 16]); // e_entry, e_phoff
+    buf.extend_from_slice(&(64 + body.len() as u64).to_le_bytes()); // e_shoff
+    buf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    for v in [64, 56, 0, 64, shnum, shnum - 1] {
+        // e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum, e_shstrndx
+        buf.extend_from_slice(&v.to_le_bytes());
+    }
+    assert_eq!(64, buf.len());
+
+    buf.extend_from_slice(&body);
+    buf.extend_from_slice(&headers);
+    buf
+}
+
+/// Build a `.deb` whose `data.tar.xz` holds the provided files.
+pub async fn deb(files: &[(&str, &[u8])]) -> Vec<u8> {
+    deb_member("data.tar.xz", &xz(&tar(files).await)).await
+}
+
+/// Build a `.deb` with an already compressed data member called `name`.
+pub async fn deb_member(name: &str, data: &[u8]) -> Vec<u8> {
+    let control = xz(&tar(&[]).await);
+    ar(&[
+        ("debian-binary", &b"2.0\n"[..]),
+        ("control.tar.xz", &control[..]),
+        (name, data),
+    ])
+}
+
+/// HTTP/1.1 server, on hyper, serving a fixed set of files, with support
+/// for HEAD and single byte-range GET requests.
+#[derive(Clone)]
+pub struct Mirror {
+    files: Arc<HashMap<String, Vec<u8>>>,
+    redirects: Arc<HashMap<String, String>>,
+    connections: Arc<AtomicUsize>,
+    requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+    failures: Arc<AtomicUsize>,
+    ranges: bool,
+    accept_ranges: bool,
+    chunked: bool,
+    overrun: usize,
+    /// gzip every body, and whether to say so on HEADs too.
+    gzip: Option<bool>,
+}
+
+impl Mirror {
+    ///
+    pub fn new(files: &[(&str, Vec<u8>)]) -> Self {
+        Self {
+            files: Arc::new(
+                files
+                    .iter()
+                    .map(|(path, body)| ((*path).to_owned(), body.clone()))
+                    .collect(),
+            ),
+            redirects: Arc::default(),
+            connections: Arc::default(),
+            requests: Arc::default(),
+            failures: Arc::default(),
+            ranges: true,
+            accept_ranges: true,
+            chunked: false,
+            overrun: 0,
+            gzip: None,
+        }
+    }
+
+    /// Headers of every request made so far, with lowercased names.
+    pub fn requests(&self) -> Vec<HashMap<String, String>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Answer the next `failures` requests with a 503.
+    pub fn with_failures(self, failures: usize) -> Self {
+        self.failures.store(failures, Ordering::SeqCst);
+        self
+    }
+
+    /// Send bodies with `transfer-encoding: chunked`, rather than a
+    /// `content-length`.
+    pub fn without_content_length(mut self) -> Self {
+        self.chunked = true;
+        self
+    }
+
+    /// Don't advertise or honor `range` requests at all.
+    pub fn without_accept_ranges(mut self) -> Self {
+        self.ranges = false;
+        self.accept_ranges = false;
+        self
+    }
+
+    /// Ignore `range` headers and always send the whole file, like a badly
+    /// behaved caching proxy. We still claim to accept ranges.
+    pub fn without_ranges(mut self) -> Self {
+        self.ranges = false;
+        self
+    }
+
+    /// Send `overrun` bytes past the end of every range asked for, while
+    /// still claiming to send just the range, like a buggy proxy. hyper
+    /// won't send more than a `content-length` says, so these go chunked.
+    pub fn with_overrun(mut self, overrun: usize) -> Self {
+        self.overrun = overrun;
+        self
+    }
+
+    /// Send every body with `content-encoding: gzip`, whatever the client
+    /// accepts, like a misconfigured proxy. Ranges are cut out of the file
+    /// and then encoded. HEADs only say so if `head`.
+    pub fn with_gzip(mut self, head: bool) -> Self {
+        self.gzip = Some(head);
+        self
+    }
+
+    /// Number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Answer requests for `from` with a redirect to `to`.
+    pub fn with_redirect(mut self, from: &str, to: &str) -> Self {
+        Arc::make_mut(&mut self.redirects).insert(from.to_owned(), to.to_owned());
+        self
+    }
+
+    /// Serve on a fresh Unix socket, returning its path.
+    pub fn listen_unix(&self) -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "debugfs-{}-{}.sock",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let mirror = self.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mirror = mirror.clone();
+                tokio::spawn(async move {
+                    let _ = mirror.serve(stream).await;
+                });
+            }
+        });
+        path
+    }
+
+    /// Serve on a fresh loopback TCP port, returning its address.
+    pub async fn listen_tcp(&self) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mirror = self.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mirror = mirror.clone();
+                tokio::spawn(async move {
+                    let _ = mirror.serve(stream).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Handle requests on a single connection until the client hangs up.
+    async fn serve<S>(&self, stream: S) -> hyper::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        self.connections.fetch_add(1, Ordering::SeqCst);
+        let mirror = self.clone();
+        let service = service_fn(move |req| {
+            let mirror = mirror.clone();
+            async move { Ok::<_, Infallible>(mirror.respond(req).await) }
+        });
+        http1::Builder::new()
+            .serve_connection(TokioIo::new(stream), service)
+            .await
+    }
+
+    ///
+    async fn respond(&self, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        let headers = req
+            .headers()
+            .iter()
+            .map(|(key, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                (key.as_str().to_owned(), value)
+            })
+            .collect();
+        self.requests.lock().unwrap().push(headers);
+
+        let response = |status: u16| Response::builder().status(status);
+        let empty = || Full::new(Bytes::new());
+
+        let failing = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| v.checked_sub(1))
+            .is_ok();
+        if failing {
+            return response(503).body(empty()).unwrap();
+        }
+
+        // the whole request-target, so that requests through a proxy can
+        // be told apart.
+        let path = req.uri().to_string();
+        if let Some(to) = self.redirects.get(&path) {
+            return response(302).header("location", to).body(empty()).unwrap();
+        }
+
+        let body = match self.files.get(&path) {
+            None => return response(404).body(empty()).unwrap(),
+            Some(v) => v,
+        };
+
+        let range = req
+            .headers()
+            .get("range")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("bytes="))
+            .and_then(|v| v.split_once('-'))
+            .and_then(|(start, end)| {
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            })
+            .filter(|_| self.ranges);
+        let mut builder = match range {
+            None => response(200),
+            Some((start, _)) if start >= body.len() => {
+                return response(416)
+                    .header("content-range", format!("bytes */{}", body.len()))
+                    .body(empty())
+                    .unwrap()
+            }
+            Some((start, end)) => {
+                let end = end.min(body.len() - 1);
+                response(206).header(
+                    "content-range",
+                    format!("bytes {start}-{end}/{}", body.len()),
+                )
+            }
+        };
+        let slice = match range {
+            None => &body[..],
+            Some((start, end)) => &body[start..=(end + self.overrun).min(body.len() - 1)],
+        };
+
+        if self.accept_ranges {
+            builder = builder.header("accept-ranges", "bytes");
+        }
+        let head = req.method() == Method::HEAD;
+        let slice = match self.gzip {
+            Some(gzip_head) if !head || gzip_head => {
+                builder = builder.header("content-encoding", "gzip");
+                gzip(slice).await
+            }
+            _ => slice.to_vec(),
+        };
+
+        // hyper leaves the body off of a HEAD, but keeps the headers.
+        builder = match self.chunked || self.overrun > 0 {
+            true => builder.header("transfer-encoding", "chunked"),
+            false => builder.header("content-length", slice.len()),
+        };
+        let slice = match head {
+            true => vec![],
+            false => slice,
+        };
+        builder.body(Full::new(Bytes::from(slice))).unwrap()
+    }
+}
+
+/// `http+unix://` URI for `path` on the server listening at `socket`.
+pub fn unix_uri(socket: &std::path::Path, path: &str) -> String {
+    let host: String = socket
+        .to_str()
+        .unwrap()
+        .bytes()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("http+unix://{host}{path}")
+}
+
+// vim: foldmethod=marker